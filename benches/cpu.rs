@@ -0,0 +1,57 @@
+//! Measures raw instruction throughput of `CPU::step` against the linear
+//! `OPCODES` scan it replaced.
+//!
+//! Run with `cargo bench --bench cpu`. A real NES executes roughly 1.79M
+//! cycles (about 600K instructions) per second, so anything well above that
+//! leaves headroom for the PPU and APU.
+
+use std::time::{Duration, Instant};
+
+use nes_emulator::cpu::cpu::{CpuError, StepResult, CPU};
+
+const INSTRUCTIONS: usize = 5_000_000;
+
+fn run<F>(mut step: F) -> Duration
+where
+    F: FnMut(&mut CPU) -> Result<StepResult, CpuError>,
+{
+    let mut cpu = CPU::new();
+    // A mixed loop touching loads, ALU ops, stores, compares and branches:
+    //   loop: LDA $10,X; ADC #$01; STA $0200,X; INX; CPX #$40; BNE loop
+    //         LDX #$00; JMP loop
+    cpu.load(vec![
+        0xB5, 0x10,
+        0x69, 0x01,
+        0x9D, 0x00, 0x02,
+        0xE8,
+        0xE0, 0x40,
+        0xD0, 0xF4,
+        0xA2, 0x00,
+        0x4C, 0x00, 0x80,
+    ]);
+    cpu.reset();
+
+    let start = Instant::now();
+    for _ in 0..INSTRUCTIONS {
+        step(&mut cpu).unwrap();
+    }
+    start.elapsed()
+}
+
+fn report(name: &str, elapsed: Duration) -> f64 {
+    let per_second = INSTRUCTIONS as f64 / elapsed.as_secs_f64();
+    println!(
+        "{:>12}: {} instructions in {:?}: {:.2}M instructions/sec",
+        name,
+        INSTRUCTIONS,
+        elapsed,
+        per_second / 1_000_000.0
+    );
+    per_second
+}
+
+fn main() {
+    let before = report("linear scan", run(CPU::step_linear_scan));
+    let after = report("table", run(CPU::step));
+    println!("{:>12}: {:.1}x", "speedup", after / before);
+}
@@ -0,0 +1,950 @@
+static LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+static DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+static TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+/// Noise timer periods in CPU cycles (NTSC).
+static NOISE_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+/// DMC output rates in CPU cycles (NTSC).
+static DMC_RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+// Frame counter steps in CPU cycles (NTSC).
+const QUARTER_FRAME_1: usize = 7457;
+const HALF_FRAME_1: usize = 14913;
+const QUARTER_FRAME_3: usize = 22371;
+const FOUR_STEP_END: usize = 29829;
+const FIVE_STEP_END: usize = 37281;
+
+/// Volume shared by the pulse and noise channels: either a constant level
+/// or a sawtooth that decays from 15 once per quarter frame.
+#[derive(Debug, Default)]
+struct Envelope {
+    start: bool,
+    looping: bool,
+    constant_volume: bool,
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, data: u8) {
+        self.looping = data & 0b0010_0000 != 0;
+        self.constant_volume = data & 0b0001_0000 != 0;
+        self.volume = data & 0b0000_1111;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+/// Silences a channel once it runs out, unless halted.
+#[derive(Debug, Default)]
+struct LengthCounter {
+    enabled: bool,
+    halted: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index >> 3) as usize];
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.halted && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+#[derive(Debug)]
+struct Pulse {
+    /// Pulse 1 negates with ones' complement, pulse 2 with twos' complement.
+    ones_complement: bool,
+    duty: u8,
+    sequence_pos: u8,
+    timer_period: u16,
+    timer: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    fn new(ones_complement: bool) -> Self {
+        Pulse {
+            ones_complement,
+            duty: 0,
+            sequence_pos: 0,
+            timer_period: 0,
+            timer: 0,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            // DDLC VVVV
+            0 => {
+                self.duty = data >> 6;
+                self.length.halted = data & 0b0010_0000 != 0;
+                self.envelope.write(data);
+            }
+            // EPPP NSSS
+            1 => {
+                self.sweep_enabled = data & 0b1000_0000 != 0;
+                self.sweep_period = (data >> 4) & 0b111;
+                self.sweep_negate = data & 0b0000_1000 != 0;
+                self.sweep_shift = data & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            // LLLL LTTT
+            3 => {
+                self.timer_period = (self.timer_period & 0x00FF) | (((data & 0b111) as u16) << 8);
+                self.length.load(data);
+                self.envelope.start = true;
+                self.sequence_pos = 0;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Clocked every other CPU cycle.
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_pos = (self.sequence_pos + 1) & 0b111;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if self.sweep_negate {
+            let complement = self.ones_complement as u16;
+            self.timer_period.saturating_sub(change + complement)
+        } else {
+            self.timer_period + change
+        }
+    }
+
+    /// The sweep unit mutes the channel whenever the period is too small or
+    /// its target overflows, even if sweeping is disabled.
+    fn is_muted(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target() > 0x7FF
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.is_muted() {
+            self.timer_period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        let high = DUTY_TABLE[self.duty as usize][self.sequence_pos as usize] != 0;
+        if high && self.length.is_active() && !self.is_muted() {
+            self.envelope.output()
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Triangle {
+    sequence_pos: u8,
+    timer_period: u16,
+    timer: u16,
+    length: LengthCounter,
+    /// Doubles as the length counter halt flag.
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+}
+
+impl Triangle {
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            // CRRR RRRR
+            0 => {
+                self.control = data & 0b1000_0000 != 0;
+                self.length.halted = self.control;
+                self.linear_reload_value = data & 0b0111_1111;
+            }
+            1 => {}
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            // LLLL LTTT
+            3 => {
+                self.timer_period = (self.timer_period & 0x00FF) | (((data & 0b111) as u16) << 8);
+                self.length.load(data);
+                self.linear_reload = true;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length.is_active() && self.linear_counter > 0 {
+                self.sequence_pos = (self.sequence_pos + 1) & 0b1_1111;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_linear_counter(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    /// The triangle never mutes; a halted sequencer keeps holding its last
+    /// level.
+    fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.sequence_pos as usize]
+    }
+}
+
+#[derive(Debug)]
+struct Noise {
+    short_mode: bool,
+    timer_period: u16,
+    timer: u16,
+    shift_register: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+}
+
+impl Noise {
+    fn new() -> Self {
+        Noise {
+            short_mode: false,
+            timer_period: NOISE_PERIOD_TABLE[0] - 1,
+            timer: 0,
+            shift_register: 1,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            // --LC VVVV
+            0 => {
+                self.length.halted = data & 0b0010_0000 != 0;
+                self.envelope.write(data);
+            }
+            1 => {}
+            // M--- PPPP
+            2 => {
+                self.short_mode = data & 0b1000_0000 != 0;
+                self.timer_period = NOISE_PERIOD_TABLE[(data & 0b1111) as usize] - 1;
+            }
+            // LLLL L---
+            3 => {
+                self.length.load(data);
+                self.envelope.start = true;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.shift_register & 1 == 0 && self.length.is_active() {
+            self.envelope.output()
+        } else {
+            0
+        }
+    }
+}
+
+/// The delta modulation channel. It plays 1-bit delta-encoded samples that
+/// it fetches from CPU memory one byte at a time; the bus owner services
+/// those fetches through `NesAPU::dmc_sample_request`.
+#[derive(Debug)]
+struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    timer_period: u16,
+    timer: u16,
+    output_level: u8,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    irq: bool,
+}
+
+impl Dmc {
+    fn new() -> Self {
+        Dmc {
+            irq_enabled: false,
+            looping: false,
+            timer_period: DMC_RATE_TABLE[0] - 1,
+            timer: 0,
+            output_level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq: false,
+        }
+    }
+
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            // IL-- RRRR
+            0 => {
+                self.irq_enabled = data & 0b1000_0000 != 0;
+                self.looping = data & 0b0100_0000 != 0;
+                self.timer_period = DMC_RATE_TABLE[(data & 0b1111) as usize] - 1;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            // -DDD DDDD
+            1 => self.output_level = data & 0b0111_1111,
+            // Sample address = %11AAAAAA.AA000000
+            2 => self.sample_address = 0xC000 | ((data as u16) << 6),
+            // Sample length = %LLLL.LLLL0001
+            3 => self.sample_length = ((data as u16) << 4) | 1,
+            _ => unreachable!(),
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn sample_request(&self) -> Option<u16> {
+        match self.sample_buffer {
+            None if self.bytes_remaining > 0 => Some(self.current_address),
+            _ => None,
+        }
+    }
+
+    fn load_sample(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        // The address wraps from $FFFF to $8000, not $0000.
+        self.current_address = match self.current_address {
+            0xFFFF => 0x8000,
+            addr => addr + 1,
+        };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period;
+
+        if !self.silence {
+            // Move the level by 2 per bit, ignoring steps that would leave
+            // the 0-127 range.
+            if self.shift_register & 1 == 1 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift_register = data;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameCounterMode {
+    FourStep,
+    FiveStep,
+}
+
+/// Current output level of each channel, before mixing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelOutputs {
+    pub pulse1: u8,
+    pub pulse2: u8,
+    pub triangle: u8,
+    pub noise: u8,
+    /// 7-bit DMC output level.
+    pub dmc: u8,
+}
+
+/// The 2A03 audio processing unit, registers $4000-$4013, $4015 and $4017.
+pub struct NesAPU {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    pub frame_counter_mode: FrameCounterMode,
+    frame_irq_inhibit: bool,
+    frame_irq: bool,
+    /// CPU cycles since the frame counter was last reset.
+    frame_cycle: usize,
+    /// Pulse timers run at half the CPU rate.
+    odd_cycle: bool,
+}
+
+impl NesAPU {
+    pub fn new() -> Self {
+        NesAPU {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_counter_mode: FrameCounterMode::FourStep,
+            frame_irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            odd_cycle: false,
+        }
+    }
+
+    /// Writes one of the channel registers, $4015 or $4017.
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write_register(addr - 0x4000, data),
+            0x4004..=0x4007 => self.pulse2.write_register(addr - 0x4004, data),
+            0x4008..=0x400B => self.triangle.write_register(addr - 0x4008, data),
+            0x400C..=0x400F => self.noise.write_register(addr - 0x400C, data),
+            0x4010..=0x4013 => self.dmc.write_register(addr - 0x4010, data),
+            0x4015 => self.write_status(data),
+            0x4017 => self.write_frame_counter(data),
+            _ => {}
+        }
+    }
+
+    // ---D NT21
+    fn write_status(&mut self, data: u8) {
+        self.pulse1.length.set_enabled(data & 0b0001 != 0);
+        self.pulse2.length.set_enabled(data & 0b0010 != 0);
+        self.triangle.length.set_enabled(data & 0b0100 != 0);
+        self.noise.length.set_enabled(data & 0b1000 != 0);
+        self.dmc.set_enabled(data & 0b1_0000 != 0);
+        self.dmc.irq = false;
+    }
+
+    /// Reads $4015. Bit 5 is not driven, so callers should fill it from
+    /// open bus. Reading acknowledges the frame interrupt.
+    pub fn read_status(&mut self) -> u8 {
+        let mut data = 0;
+        data |= self.pulse1.length.is_active() as u8;
+        data |= (self.pulse2.length.is_active() as u8) << 1;
+        data |= (self.triangle.length.is_active() as u8) << 2;
+        data |= (self.noise.length.is_active() as u8) << 3;
+        data |= ((self.dmc.bytes_remaining > 0) as u8) << 4;
+        data |= (self.frame_irq as u8) << 6;
+        data |= (self.dmc.irq as u8) << 7;
+        self.frame_irq = false;
+        data
+    }
+
+    // MI-- ----
+    fn write_frame_counter(&mut self, data: u8) {
+        self.frame_counter_mode = if data & 0b1000_0000 != 0 {
+            FrameCounterMode::FiveStep
+        } else {
+            FrameCounterMode::FourStep
+        };
+        self.frame_irq_inhibit = data & 0b0100_0000 != 0;
+        if self.frame_irq_inhibit {
+            self.frame_irq = false;
+        }
+
+        self.frame_cycle = 0;
+        if self.frame_counter_mode == FrameCounterMode::FiveStep {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+        }
+    }
+
+    /// Level of the APU's IRQ output.
+    pub fn irq_line(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    /// Address the DMC wants its next sample byte from, if its buffer is
+    /// empty. The bus owner reads it, hands it to `dmc_load_sample` and
+    /// stalls the CPU for the stolen cycles.
+    pub fn dmc_sample_request(&self) -> Option<u16> {
+        self.dmc.sample_request()
+    }
+
+    pub fn dmc_load_sample(&mut self, data: u8) {
+        self.dmc.load_sample(data);
+    }
+
+    pub fn outputs(&self) -> ChannelOutputs {
+        ChannelOutputs {
+            pulse1: self.pulse1.output(),
+            pulse2: self.pulse2.output(),
+            triangle: self.triangle.output(),
+            noise: self.noise.output(),
+            dmc: self.dmc.output_level,
+        }
+    }
+
+    /// Advances the APU by `cycles` CPU cycles.
+    pub fn tick(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            self.step_cycle();
+        }
+    }
+
+    fn step_cycle(&mut self) {
+        self.frame_cycle += 1;
+        self.clock_frame_counter();
+
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+    }
+
+    fn clock_frame_counter(&mut self) {
+        match (self.frame_counter_mode, self.frame_cycle) {
+            (_, QUARTER_FRAME_1) | (_, QUARTER_FRAME_3) => self.clock_quarter_frame(),
+            (_, HALF_FRAME_1) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            (FrameCounterMode::FourStep, FOUR_STEP_END) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+                if !self.frame_irq_inhibit {
+                    self.frame_irq = true;
+                }
+                self.frame_cycle = 0;
+            }
+            (FrameCounterMode::FiveStep, FIVE_STEP_END) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+                self.frame_cycle = 0;
+            }
+            _ => {}
+        }
+    }
+
+    /// Envelopes and the triangle's linear counter.
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear_counter();
+    }
+
+    /// Length counters and sweep units.
+    fn clock_half_frame(&mut self) {
+        self.pulse1.length.clock();
+        self.pulse2.length.clock();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+        self.pulse1.clock_sweep();
+        self.pulse2.clock_sweep();
+    }
+}
+
+impl Default for NesAPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_length_counter_loads_only_when_enabled() {
+        let mut apu = NesAPU::new();
+        apu.write_register(0x4003, 0b0000_1000);
+        assert_eq!(apu.read_status() & 0b1, 0);
+
+        apu.write_register(0x4015, 0b0000_1111);
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.write_register(0x400B, 0b0000_1000);
+        assert_eq!(apu.pulse1.length.counter, 254);
+        assert_eq!(apu.read_status() & 0b1111, 0b0101);
+
+        apu.write_register(0x4015, 0b0000_0100);
+        assert_eq!(apu.read_status() & 0b1111, 0b0100);
+    }
+
+    #[test]
+    fn test_length_counter_clocks_on_half_frames() {
+        let mut apu = NesAPU::new();
+        apu.write_register(0x4015, 0b0000_0011);
+        apu.write_register(0x4003, 0b0001_1000); // length 2
+        apu.write_register(0x4004, 0b0010_0000); // halted
+        apu.write_register(0x4007, 0b0001_1000);
+
+        apu.tick(HALF_FRAME_1);
+        assert_eq!(apu.pulse1.length.counter, 1);
+        apu.tick(FOUR_STEP_END - HALF_FRAME_1);
+        assert_eq!(apu.read_status() & 0b11, 0b10);
+    }
+
+    #[test]
+    fn test_frame_irq_in_four_step_mode() {
+        let mut apu = NesAPU::new();
+        apu.tick(FOUR_STEP_END - 1);
+        assert!(!apu.irq_line());
+        apu.tick(1);
+        assert!(apu.irq_line());
+
+        assert_eq!(apu.read_status() & 0b0100_0000, 0b0100_0000);
+        assert!(!apu.irq_line());
+    }
+
+    #[test]
+    fn test_frame_irq_inhibit_and_five_step_mode() {
+        let mut apu = NesAPU::new();
+        apu.tick(FOUR_STEP_END);
+        apu.write_register(0x4017, 0b0100_0000);
+        assert!(!apu.irq_line());
+        apu.tick(FOUR_STEP_END);
+        assert!(!apu.irq_line());
+
+        apu.write_register(0x4017, 0b1000_0000);
+        apu.tick(FIVE_STEP_END * 2);
+        assert!(!apu.irq_line());
+    }
+
+    #[test]
+    fn test_five_step_write_clocks_half_frame_immediately() {
+        let mut apu = NesAPU::new();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4003, 0b0001_1000); // length 2
+        apu.write_register(0x4017, 0b1000_0000);
+        assert_eq!(apu.pulse1.length.counter, 1);
+    }
+
+    #[test]
+    fn test_envelope_decays_and_loops() {
+        let mut envelope = Envelope::default();
+        envelope.write(0b0010_0000); // loop, period 0
+        envelope.start = true;
+
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        for _ in 0..15 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+
+        envelope.write(0b0001_0111);
+        assert_eq!(envelope.output(), 7);
+    }
+
+    #[test]
+    fn test_pulse_output_and_sweep_muting() {
+        let mut apu = NesAPU::new();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b1101_1010); // 75% duty, constant volume 10
+        apu.write_register(0x4002, 0x07);
+        apu.write_register(0x4003, 0b0000_1000);
+        // Period 7 is too small and mutes the channel.
+        assert_eq!(apu.outputs().pulse1, 0);
+
+        apu.write_register(0x4002, 0x08);
+        assert_eq!(apu.outputs().pulse1, 10);
+
+        // With shift 0, period $400 targets $800, which mutes the channel
+        // even though sweeping is disabled.
+        apu.write_register(0x4002, 0x00);
+        apu.write_register(0x4003, 0b0000_1100);
+        assert_eq!(apu.outputs().pulse1, 0);
+        apu.write_register(0x4001, 0b0000_0001);
+        assert_eq!(apu.outputs().pulse1, 10);
+    }
+
+    #[test]
+    fn test_sweep_negate_differs_between_pulses() {
+        let mut pulse1 = Pulse::new(true);
+        let mut pulse2 = Pulse::new(false);
+        for pulse in [&mut pulse1, &mut pulse2] {
+            pulse.write_register(2, 0x00);
+            pulse.write_register(3, 0x01);
+            pulse.write_register(1, 0b1000_1001); // enabled, period 0, negate, shift 1
+            pulse.clock_sweep();
+        }
+        assert_eq!(pulse1.timer_period, 0x100 - 0x80 - 1);
+        assert_eq!(pulse2.timer_period, 0x100 - 0x80);
+    }
+
+    #[test]
+    fn test_triangle_needs_linear_and_length_counters() {
+        let mut apu = NesAPU::new();
+        apu.write_register(0x4015, 0b0000_0100);
+        apu.write_register(0x4008, 0b0000_0000); // linear reload 0
+        apu.write_register(0x400A, 0x00);
+        apu.write_register(0x400B, 0b0000_1000);
+        apu.tick(QUARTER_FRAME_1);
+        assert_eq!(apu.triangle.sequence_pos, 0);
+
+        apu.write_register(0x4008, 0b0000_0100); // linear reload 4
+        apu.write_register(0x400B, 0b0000_1000);
+        apu.tick(QUARTER_FRAME_1);
+        let pos = apu.triangle.sequence_pos;
+        apu.tick(4);
+        assert_eq!(apu.triangle.sequence_pos, (pos + 4) & 0b1_1111);
+    }
+
+    #[test]
+    fn test_noise_shift_register() {
+        let mut noise = Noise::new();
+        noise.clock_timer();
+        // Bits 0 and 1 differ, so the feedback bit is set.
+        assert_eq!(noise.shift_register, 0b100_0000_0000_0000);
+
+        let mut noise = Noise::new();
+        noise.write_register(2, 0b1000_0000);
+        noise.shift_register = 0b100_0000;
+        noise.clock_timer();
+        assert_eq!(noise.shift_register, 0b100_0000_0010_0000);
+    }
+
+    /// Services DMC fetches from `memory` (indexed from $C000) the way the
+    /// bus would, returning how many were made.
+    fn tick_with_memory(apu: &mut NesAPU, memory: &[u8], cycles: usize) -> usize {
+        let mut fetches = 0;
+        for _ in 0..cycles {
+            apu.tick(1);
+            if let Some(addr) = apu.dmc_sample_request() {
+                apu.dmc_load_sample(memory[(addr - 0xC000) as usize]);
+                fetches += 1;
+            }
+        }
+        fetches
+    }
+
+    #[test]
+    fn test_dmc_sample_address_and_length() {
+        let mut apu = NesAPU::new();
+        apu.write_register(0x4012, 0x01);
+        apu.write_register(0x4013, 0x02);
+        apu.write_register(0x4015, 0b0001_0000);
+
+        assert_eq!(apu.dmc_sample_request(), Some(0xC040));
+        assert_eq!(apu.dmc.bytes_remaining, 0x21);
+        assert_eq!(apu.read_status() & 0b0001_0000, 0b0001_0000);
+
+        apu.write_register(0x4015, 0);
+        assert_eq!(apu.dmc_sample_request(), None);
+        assert_eq!(apu.read_status() & 0b0001_0000, 0);
+    }
+
+    #[test]
+    fn test_dmc_output_unit_follows_sample_bits() {
+        let mut apu = NesAPU::new();
+        apu.write_register(0x4010, 0x0F); // fastest rate, 54 cycles per bit
+        apu.write_register(0x4011, 0x40);
+        apu.write_register(0x4015, 0b0001_0000);
+
+        // The first byte waits for the current (silent) output cycle to end.
+        assert_eq!(tick_with_memory(&mut apu, &[0b0000_0011], 8 * 54), 1);
+        assert_eq!(apu.outputs().dmc, 0x40);
+
+        tick_with_memory(&mut apu, &[0], 2 * 54);
+        assert_eq!(apu.outputs().dmc, 0x44);
+        tick_with_memory(&mut apu, &[0], 6 * 54);
+        assert_eq!(apu.outputs().dmc, 0x38);
+    }
+
+    #[test]
+    fn test_dmc_output_level_stays_in_range() {
+        let mut dmc = Dmc::new();
+        dmc.silence = false;
+        dmc.output_level = 126;
+        dmc.shift_register = 0xFF;
+        dmc.clock_timer();
+        assert_eq!(dmc.output_level, 126);
+
+        dmc.output_level = 1;
+        dmc.shift_register = 0x00;
+        dmc.clock_timer();
+        assert_eq!(dmc.output_level, 1);
+    }
+
+    #[test]
+    fn test_dmc_irq_at_end_of_sample() {
+        let mut apu = NesAPU::new();
+        apu.write_register(0x4010, 0b1000_1111);
+        apu.write_register(0x4013, 0x01); // 17 bytes
+        apu.write_register(0x4015, 0b0001_0000);
+
+        let fetches = tick_with_memory(&mut apu, &[0; 17], 17 * 8 * 54);
+        assert_eq!(fetches, 17);
+        assert!(apu.irq_line());
+        assert_eq!(apu.read_status() & 0b1001_0000, 0b1000_0000);
+
+        // Acknowledged by writing $4015.
+        apu.write_register(0x4015, 0);
+        assert!(!apu.irq_line());
+    }
+
+    #[test]
+    fn test_dmc_loop_restarts_without_irq() {
+        let mut apu = NesAPU::new();
+        apu.write_register(0x4010, 0b1100_1111);
+        apu.write_register(0x4015, 0b0001_0000);
+
+        let fetches = tick_with_memory(&mut apu, &[0; 1], 5 * 8 * 54);
+        assert!(fetches >= 4);
+        assert!(!apu.irq_line());
+        assert_eq!(apu.dmc.bytes_remaining, 1);
+    }
+
+    #[test]
+    fn test_dmc_address_wraps_to_8000() {
+        let mut dmc = Dmc::new();
+        dmc.current_address = 0xFFFF;
+        dmc.bytes_remaining = 2;
+        dmc.load_sample(0);
+        assert_eq!(dmc.current_address, 0x8000);
+    }
+}
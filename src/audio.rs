@@ -0,0 +1,418 @@
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::apu::ChannelOutputs;
+
+/// NTSC CPU clock in Hz; the APU produces one sample per CPU cycle.
+pub const CPU_CLOCK_NTSC: f64 = 1_789_772.727;
+
+/// The console's analog output stage, as first-order filters in series.
+const HIGH_PASS_1_HZ: f64 = 90.0;
+const HIGH_PASS_2_HZ: f64 = 440.0;
+const LOW_PASS_HZ: f64 = 14_000.0;
+
+/// The 2A03's non-linear DAC, as the usual pair of lookup tables: one for
+/// the sum of the pulse levels and one for triangle, noise and DMC.
+pub struct Mixer {
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        let mut pulse_table = [0.0; 31];
+        for (n, entry) in pulse_table.iter_mut().enumerate().skip(1) {
+            *entry = (95.52 / (8128.0 / n as f64 + 100.0)) as f32;
+        }
+        let mut tnd_table = [0.0; 203];
+        for (n, entry) in tnd_table.iter_mut().enumerate().skip(1) {
+            *entry = (163.67 / (24329.0 / n as f64 + 100.0)) as f32;
+        }
+        Mixer {
+            pulse_table,
+            tnd_table,
+        }
+    }
+
+    /// Mixes the channel levels into a sample between 0.0 and about 1.0.
+    pub fn mix(&self, outputs: &ChannelOutputs) -> f32 {
+        let pulse = (outputs.pulse1 + outputs.pulse2) as usize;
+        let tnd = 3 * outputs.triangle as usize + 2 * outputs.noise as usize + outputs.dmc as usize;
+        self.pulse_table[pulse] + self.tnd_table[tnd]
+    }
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FilterKind {
+    HighPass,
+    LowPass,
+}
+
+/// A first-order RC filter.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    kind: FilterKind,
+    alpha: f32,
+    prev_input: f32,
+    prev_output: f32,
+}
+
+impl Filter {
+    pub fn high_pass(sample_rate: f64, cutoff: f64) -> Self {
+        let (rc, dt) = Filter::rc_and_dt(sample_rate, cutoff);
+        Filter::new(FilterKind::HighPass, (rc / (rc + dt)) as f32)
+    }
+
+    pub fn low_pass(sample_rate: f64, cutoff: f64) -> Self {
+        let (rc, dt) = Filter::rc_and_dt(sample_rate, cutoff);
+        Filter::new(FilterKind::LowPass, (dt / (rc + dt)) as f32)
+    }
+
+    fn rc_and_dt(sample_rate: f64, cutoff: f64) -> (f64, f64) {
+        (1.0 / (2.0 * PI * cutoff), 1.0 / sample_rate)
+    }
+
+    fn new(kind: FilterKind, alpha: f32) -> Self {
+        Filter {
+            kind,
+            alpha,
+            prev_input: 0.0,
+            prev_output: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = match self.kind {
+            FilterKind::HighPass => self.alpha * (self.prev_output + input - self.prev_input),
+            FilterKind::LowPass => self.prev_output + self.alpha * (input - self.prev_output),
+        };
+        self.prev_input = input;
+        self.prev_output = output;
+        output
+    }
+}
+
+/// Width of the resampler's band-limited step, in output samples.
+const RESAMPLER_TAPS: usize = 32;
+/// Fractional positions the step is tabulated at between output samples.
+const RESAMPLER_PHASES: usize = 64;
+/// Cutoff as a fraction of the output rate. The Blackman window's
+/// transition band is about 6/RESAMPLER_TAPS wide, so this puts the
+/// stopband just above the output Nyquist rate.
+const RESAMPLER_CUTOFF: f64 = 0.45;
+
+/// Downsamples with band-limited steps. The mixed output only changes level
+/// on some cycles, so rather than filtering every input sample, each change
+/// adds a windowed-sinc impulse scaled by the change at its fractional
+/// position between output samples, and the output is the running sum. This
+/// is a polyphase FIR low-pass at just under the output Nyquist rate. Output
+/// lags input by half the kernel width.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    /// Output samples per input sample.
+    step: f64,
+    /// Time of the next input sample since the last output, in output
+    /// samples.
+    time: f64,
+    level: f32,
+    /// `RESAMPLER_PHASES + 1` rows of taps, each summing to one so a step
+    /// settles at exactly its height.
+    kernel: Vec<[f64; RESAMPLER_TAPS]>,
+    /// Pending changes in level, one slot per upcoming output sample.
+    deltas: [f64; RESAMPLER_TAPS],
+    /// The slot in `deltas` of the next output sample.
+    head: usize,
+    output: f64,
+}
+
+impl Resampler {
+    pub fn new(input_rate: f64, output_rate: f64) -> Self {
+        let half = (RESAMPLER_TAPS / 2) as f64;
+        let kernel = (0..=RESAMPLER_PHASES)
+            .map(|phase| {
+                let offset = phase as f64 / RESAMPLER_PHASES as f64;
+                let mut taps = [0.0; RESAMPLER_TAPS];
+                for (i, tap) in taps.iter_mut().enumerate() {
+                    // Slot i is output i + 1 - offset samples after the change.
+                    let x = i as f64 + 1.0 - offset - half;
+                    let window =
+                        0.42 + 0.5 * (PI * x / half).cos() + 0.08 * (2.0 * PI * x / half).cos();
+                    let y = 2.0 * RESAMPLER_CUTOFF * x;
+                    let sinc = if y == 0.0 { 1.0 } else { (PI * y).sin() / (PI * y) };
+                    *tap = window * sinc;
+                }
+                let sum: f64 = taps.iter().sum();
+                taps.iter_mut().for_each(|tap| *tap /= sum);
+                taps
+            })
+            .collect();
+
+        Resampler {
+            step: output_rate / input_rate,
+            time: 0.0,
+            level: 0.0,
+            kernel,
+            deltas: [0.0; RESAMPLER_TAPS],
+            head: 0,
+            output: 0.0,
+        }
+    }
+
+    /// Feeds one input sample, returning an output sample when one is
+    /// complete.
+    pub fn push(&mut self, sample: f32) -> Option<f32> {
+        if sample != self.level {
+            self.add_step((sample - self.level) as f64);
+            self.level = sample;
+        }
+
+        self.time += self.step;
+        if self.time < 1.0 {
+            return None;
+        }
+        self.time -= 1.0;
+        self.output += self.deltas[self.head];
+        self.deltas[self.head] = 0.0;
+        self.head = (self.head + 1) % RESAMPLER_TAPS;
+        Some(self.output as f32)
+    }
+
+    /// Spreads a change in level over the pending output samples,
+    /// interpolating between the two nearest tabulated phases.
+    fn add_step(&mut self, delta: f64) {
+        let position = self.time * RESAMPLER_PHASES as f64;
+        let phase = (position as usize).min(RESAMPLER_PHASES - 1);
+        let weight = position - phase as f64;
+        let (before, after) = (&self.kernel[phase], &self.kernel[phase + 1]);
+        for i in 0..RESAMPLER_TAPS {
+            let tap = before[i] + (after[i] - before[i]) * weight;
+            self.deltas[(self.head + i) % RESAMPLER_TAPS] += delta * tap;
+        }
+    }
+}
+
+/// Turns per-cycle channel levels into filtered PCM at the host rate.
+pub struct AudioPipeline {
+    pub sample_rate: u32,
+    mixer: Mixer,
+    resampler: Resampler,
+    filters: [Filter; 3],
+    samples: Vec<f32>,
+}
+
+impl AudioPipeline {
+    pub fn new(input_rate: f64, sample_rate: u32) -> Self {
+        let output_rate = sample_rate as f64;
+        AudioPipeline {
+            sample_rate,
+            mixer: Mixer::new(),
+            resampler: Resampler::new(input_rate, output_rate),
+            filters: [
+                Filter::high_pass(output_rate, HIGH_PASS_1_HZ),
+                Filter::high_pass(output_rate, HIGH_PASS_2_HZ),
+                Filter::low_pass(output_rate, LOW_PASS_HZ),
+            ],
+            samples: Vec::new(),
+        }
+    }
+
+    /// Feeds the channel levels for one CPU cycle.
+    pub fn push(&mut self, outputs: &ChannelOutputs) {
+        let sample = self.mixer.mix(outputs);
+        if let Some(sample) = self.resampler.push(sample) {
+            let filtered = self
+                .filters
+                .iter_mut()
+                .fold(sample, |sample, filter| filter.process(sample));
+            self.samples.push(filtered);
+        }
+    }
+
+    /// Takes the samples produced so far, roughly between -1.0 and 1.0.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+}
+
+/// Somewhere to send finished samples.
+pub trait AudioSink {
+    fn write_samples(&mut self, samples: &[f32]);
+}
+
+/// A headless sink that collects samples and saves them as a 16-bit mono
+/// WAV file.
+pub struct WavSink {
+    sample_rate: u32,
+    samples: Vec<i16>,
+}
+
+impl WavSink {
+    pub fn new(sample_rate: u32) -> Self {
+        WavSink {
+            sample_rate,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let data_size = (self.samples.len() * 2) as u32;
+        let channels: u16 = 1;
+        let bits_per_sample: u16 = 16;
+        let block_align = channels * bits_per_sample / 8;
+        let byte_rate = self.sample_rate * block_align as u32;
+
+        out.write_all(b"RIFF")?;
+        out.write_all(&(36 + data_size).to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+
+        out.write_all(b"fmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&channels.to_le_bytes())?;
+        out.write_all(&self.sample_rate.to_le_bytes())?;
+        out.write_all(&byte_rate.to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        out.write_all(&bits_per_sample.to_le_bytes())?;
+
+        out.write_all(b"data")?;
+        out.write_all(&data_size.to_le_bytes())?;
+        for sample in &self.samples {
+            out.write_all(&sample.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_to(&mut out)?;
+        out.flush()
+    }
+}
+
+impl AudioSink for WavSink {
+    fn write_samples(&mut self, samples: &[f32]) {
+        self.samples.extend(
+            samples
+                .iter()
+                .map(|sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mixer_tables() {
+        let mixer = Mixer::new();
+        assert_eq!(mixer.mix(&ChannelOutputs::default()), 0.0);
+
+        let loudest = ChannelOutputs {
+            pulse1: 15,
+            pulse2: 15,
+            triangle: 15,
+            noise: 15,
+            dmc: 127,
+        };
+        let pulse = 95.52 / (8128.0 / 30.0 + 100.0);
+        let tnd = 163.67 / (24329.0 / 202.0 + 100.0);
+        assert!((mixer.mix(&loudest) as f64 - (pulse + tnd)).abs() < 1e-6);
+
+        // Non-linear: two pulses together are quieter than twice one.
+        let one = mixer.mix(&ChannelOutputs {
+            pulse1: 15,
+            ..Default::default()
+        });
+        let two = mixer.mix(&ChannelOutputs {
+            pulse1: 15,
+            pulse2: 15,
+            ..Default::default()
+        });
+        assert!(two < one * 2.0);
+    }
+
+    #[test]
+    fn test_resampler_rate_and_dc() {
+        let mut resampler = Resampler::new(CPU_CLOCK_NTSC, 44_100.0);
+        let outputs: Vec<f32> = (0..CPU_CLOCK_NTSC as usize)
+            .filter_map(|_| resampler.push(0.5))
+            .collect();
+
+        assert!((44_099..=44_100).contains(&outputs.len()));
+        // The first step takes the kernel width to settle.
+        assert!(outputs[RESAMPLER_TAPS..]
+            .iter()
+            .all(|&sample| (sample - 0.5).abs() < 1e-6));
+    }
+
+    /// Peak output level once the kernel has settled, for a sine of
+    /// `frequency` and amplitude 0.5 run at the CPU rate.
+    fn resampled_sine_peak(frequency: f64) -> f32 {
+        let mut resampler = Resampler::new(CPU_CLOCK_NTSC, 44_100.0);
+        (0..CPU_CLOCK_NTSC as usize / 10)
+            .filter_map(|n| {
+                let t = n as f64 / CPU_CLOCK_NTSC;
+                resampler.push((0.5 * (2.0 * PI * frequency * t).sin()) as f32)
+            })
+            .skip(RESAMPLER_TAPS)
+            .fold(0.0, |peak, sample| sample.abs().max(peak))
+    }
+
+    #[test]
+    fn test_resampler_passband_and_stopband() {
+        let passband = resampled_sine_peak(1_000.0);
+        assert!((passband - 0.5).abs() < 0.005, "1kHz peak {}", passband);
+
+        // At least 60dB down. A box average over each output period only
+        // takes 5 to 14dB off these, and they alias into the audible band.
+        for frequency in [26_000.0, 30_000.0, 60_000.0] {
+            let stopband = resampled_sine_peak(frequency);
+            assert!(stopband < 0.5e-3, "{}Hz peak {}", frequency, stopband);
+        }
+    }
+
+    #[test]
+    fn test_filters() {
+        let mut high_pass = Filter::high_pass(44_100.0, 90.0);
+        let mut low_pass = Filter::low_pass(44_100.0, 14_000.0);
+        let mut last = (0.0, 0.0);
+        for _ in 0..44_100 {
+            last = (high_pass.process(1.0), low_pass.process(1.0));
+        }
+        assert!(last.0.abs() < 1e-3);
+        assert!((last.1 - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_wav_output() {
+        let mut sink = WavSink::new(48_000);
+        sink.write_samples(&[0.0, 1.0, -2.0]);
+        assert_eq!(sink.samples(), &[0, i16::MAX, -i16::MAX]);
+
+        let mut wav = Vec::new();
+        sink.write_to(&mut wav).unwrap();
+        assert_eq!(wav.len(), 44 + 6);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[4..8], &(36u32 + 6).to_le_bytes());
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(&wav[24..28], &48_000u32.to_le_bytes());
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(&wav[40..44], &6u32.to_le_bytes());
+        assert_eq!(&wav[44..46], &0i16.to_le_bytes());
+        assert_eq!(&wav[46..48], &i16::MAX.to_le_bytes());
+    }
+}
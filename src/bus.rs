@@ -0,0 +1,538 @@
+use crate::apu::NesAPU;
+use crate::audio::{AudioPipeline, CPU_CLOCK_NTSC};
+use crate::cartridge::{Rom, RomError};
+use crate::joypad::Joypad;
+use crate::mapper::{self, SharedMapper};
+use crate::ppu::NesPPU;
+
+/// Everything the CPU sees on its address bus. `CPU` only ever talks to
+/// memory and devices through this trait, so the same core can run against
+/// flat RAM in tests, the NES memory map, or a custom device layout.
+///
+/// Reads take `&mut self` because on real hardware they can have side
+/// effects, such as clearing the PPU vblank flag.
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, data: u8);
+
+    /// Called after every instruction with the CPU cycles it took, so
+    /// devices clocked alongside the CPU can catch up.
+    fn tick(&mut self, _cycles: usize) {}
+
+    /// Cycles the CPU has to halt for DMA started since the last call.
+    /// `cpu_cycles` is the CPU's cycle count when the halt would begin.
+    fn take_stall_cycles(&mut self, _cpu_cycles: usize) -> usize {
+        0
+    }
+
+    /// Level of the NMI input the bus drives into the CPU.
+    fn nmi_line(&self) -> bool {
+        false
+    }
+
+    /// Level of the IRQ input the bus drives into the CPU.
+    fn irq_line(&self) -> bool {
+        false
+    }
+}
+
+/// 64KB of plain read/write memory with no devices mapped.
+pub struct FlatMemory {
+    memory: [u8; 0x10000],
+}
+
+impl FlatMemory {
+    pub fn new() -> Self {
+        FlatMemory {
+            memory: [0; 0x10000],
+        }
+    }
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for FlatMemory {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+    }
+}
+
+//  _______________ $10000  _______________
+// | PRG-ROM       |       |               |
+// | Upper Bank    |       |               |
+// |_ _ _ _ _ _ _ _| $C000 | PRG-ROM       |
+// | PRG-ROM       |       |               |
+// | Lower Bank    |       |               |
+// |_______________| $8000 |_______________|
+// | SRAM          |       | SRAM          |
+// |_______________| $6000 |_______________|
+// | Expansion ROM |       | Expansion ROM |
+// |_______________| $4020 |_______________|
+// | I/O Registers |       |               |
+// |_ _ _ _ _ _ _ _| $4000 |               |
+// | Mirrors       |       | I/O Registers |
+// | $2000-$2007   |       |               |
+// |_ _ _ _ _ _ _ _| $2008 |               |
+// | I/O Registers |       |               |
+// |_______________| $2000 |_______________|
+// | Mirrors       |       |               |
+// | $0000-$07FF   |       |               |
+// |_ _ _ _ _ _ _ _| $0800 |               |
+// | RAM           |       | RAM           |
+// |_ _ _ _ _ _ _ _| $0200 |               |
+// | Stack         |       |               |
+// |_ _ _ _ _ _ _ _| $0100 |               |
+// | Zero Page     |       |               |
+// |_______________| $0000 |_______________|
+const RAM: u16 = 0x0000;
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const PPU_REGISTERS: u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const APU_IO_REGISTERS: u16 = 0x4000;
+const APU_IO_REGISTERS_END: u16 = 0x4017;
+const OAM_DMA: u16 = 0x4014;
+const APU_STATUS: u16 = 0x4015;
+const JOYPAD1: u16 = 0x4016;
+const JOYPAD2: u16 = 0x4017;
+const CARTRIDGE_SPACE: u16 = 0x4020;
+const CARTRIDGE_SPACE_END: u16 = 0xFFFF;
+
+/// CPU cycles lost to each DMC sample fetch.
+const DMC_FETCH_STALL: usize = 4;
+
+/// The NES CPU memory map.
+///
+/// Nothing drives the data bus for unmapped addresses, so reads from them
+/// return whatever value was last on the bus ("open bus").
+pub struct NesBus {
+    cpu_vram: [u8; 2048],
+    mapper: SharedMapper,
+    battery: bool,
+    ppu: NesPPU,
+    apu: NesAPU,
+    audio: Option<AudioPipeline>,
+    joypad1: Joypad,
+    joypad2: Joypad,
+    open_bus: u8,
+    /// Whether the previous CPU bus cycle was a write.
+    last_cycle_was_write: bool,
+    oam_dma_pending: bool,
+    dmc_stall_cycles: usize,
+}
+
+impl NesBus {
+    /// Plugs in `rom`, failing if its board is not emulated.
+    pub fn new(rom: Rom) -> Result<Self, RomError> {
+        let battery = rom.info.battery;
+        let mapper = mapper::from_rom(rom)?;
+        let ppu = NesPPU::new(mapper.clone());
+
+        Ok(NesBus {
+            cpu_vram: [0; 2048],
+            mapper,
+            battery,
+            ppu,
+            apu: NesAPU::new(),
+            audio: None,
+            joypad1: Joypad::new(),
+            joypad2: Joypad::new(),
+            open_bus: 0,
+            last_cycle_was_write: false,
+            oam_dma_pending: false,
+            dmc_stall_cycles: 0,
+        })
+    }
+
+    /// The cartridge board, shared with the PPU.
+    pub fn mapper(&self) -> SharedMapper {
+        self.mapper.clone()
+    }
+
+    /// Whether the cartridge keeps its PRG-RAM alive with a battery. Such
+    /// games expect it saved with a `save::BatterySave`.
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    pub fn ppu(&self) -> &NesPPU {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut NesPPU {
+        &mut self.ppu
+    }
+
+    pub fn apu(&self) -> &NesAPU {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut NesAPU {
+        &mut self.apu
+    }
+
+    /// The controller in port 1. Update its buttons once per frame.
+    pub fn joypad1_mut(&mut self) -> &mut Joypad {
+        &mut self.joypad1
+    }
+
+    /// The controller in port 2.
+    pub fn joypad2_mut(&mut self) -> &mut Joypad {
+        &mut self.joypad2
+    }
+
+    /// Starts producing audio at `sample_rate` Hz. Collect it with
+    /// `take_audio_samples`.
+    pub fn enable_audio(&mut self, sample_rate: u32) {
+        self.audio = Some(AudioPipeline::new(CPU_CLOCK_NTSC, sample_rate));
+    }
+
+    /// Samples produced since the last call; empty while audio is off.
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.audio
+            .as_mut()
+            .map_or_else(Vec::new, AudioPipeline::take_samples)
+    }
+
+    /// Copies page `$XX00-$XXFF` into OAM through OAMDATA, so the copy
+    /// starts at the current OAMADDR.
+    fn oam_dma(&mut self, page: u8) {
+        let base = (page as u16) << 8;
+        for offset in 0..256 {
+            let data = self.read(base + offset);
+            self.ppu.write_register(0x2004, data);
+        }
+        self.oam_dma_pending = true;
+    }
+}
+
+impl Bus for NesBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.last_cycle_was_write = false;
+        let data = match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b0000_0111_1111_1111;
+                Some(self.cpu_vram[mirror_down_addr as usize])
+            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => Some(self.ppu.read_register(addr)),
+            // Bit 5 of $4015 is not driven.
+            APU_STATUS => Some(self.apu.read_status() | (self.open_bus & 0b0010_0000)),
+            // Controllers only drive the low bits.
+            JOYPAD1 => Some(self.joypad1.read() | (self.open_bus & 0b1110_0000)),
+            JOYPAD2 => Some(self.joypad2.read() | (self.open_bus & 0b1110_0000)),
+            // The channel registers are write-only.
+            APU_IO_REGISTERS..=APU_IO_REGISTERS_END => None,
+            CARTRIDGE_SPACE..=CARTRIDGE_SPACE_END => self.mapper.borrow_mut().cpu_read(addr),
+            // $4018-$401F CPU test mode.
+            _ => None,
+        };
+
+        if let Some(data) = data {
+            self.open_bus = data;
+        }
+        self.open_bus
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+        let consecutive = std::mem::replace(&mut self.last_cycle_was_write, true);
+
+        match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b0000_0111_1111_1111;
+                self.cpu_vram[mirror_down_addr as usize] = data;
+            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => self.ppu.write_register(addr, data),
+            OAM_DMA => self.oam_dma(data),
+            // Strobe is wired to both ports; $4017 writes go to the APU.
+            JOYPAD1 => {
+                self.joypad1.write(data);
+                self.joypad2.write(data);
+            }
+            APU_IO_REGISTERS..=APU_IO_REGISTERS_END => self.apu.write_register(addr, data),
+            CARTRIDGE_SPACE..=CARTRIDGE_SPACE_END if consecutive => {
+                self.mapper.borrow_mut().cpu_write_consecutive(addr, data)
+            }
+            CARTRIDGE_SPACE..=CARTRIDGE_SPACE_END => self.mapper.borrow_mut().cpu_write(addr, data),
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        self.ppu.tick(cycles * 3);
+        for _ in 0..cycles {
+            self.apu.tick(1);
+            if let Some(audio) = &mut self.audio {
+                audio.push(&self.apu.outputs());
+            }
+            if let Some(addr) = self.apu.dmc_sample_request() {
+                let data = self.read(addr);
+                self.apu.dmc_load_sample(data);
+                self.dmc_stall_cycles += DMC_FETCH_STALL;
+            }
+        }
+    }
+
+    fn take_stall_cycles(&mut self, cpu_cycles: usize) -> usize {
+        let mut stall = std::mem::take(&mut self.dmc_stall_cycles);
+        if std::mem::take(&mut self.oam_dma_pending) {
+            // One dummy cycle, plus one more to align when starting on an
+            // odd cycle, then 256 read/write pairs.
+            stall += if cpu_cycles % 2 == 1 { 514 } else { 513 };
+        }
+        stall
+    }
+
+    fn nmi_line(&self) -> bool {
+        self.ppu.nmi_line()
+    }
+
+    fn irq_line(&self) -> bool {
+        self.apu.irq_line() || self.mapper.borrow().irq_line()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::cpu::cpu::CPU;
+    use crate::joypad::JoypadButton;
+    use crate::mapper::test::banked_rom;
+
+    #[test]
+    fn test_flat_memory_covers_full_address_space() {
+        let mut memory = FlatMemory::new();
+        memory.write(0x0000, 0x11);
+        memory.write(0xFFFF, 0x22);
+        assert_eq!(memory.read(0x0000), 0x11);
+        assert_eq!(memory.read(0xFFFF), 0x22);
+    }
+
+    #[test]
+    fn test_rom_boots_through_reset_vector() {
+        // LDA #$05; STA $10; BRK
+        let rom = test_rom(&[0xA9, 0x05, 0x85, 0x10, 0x00]);
+        let mut cpu = CPU::with_bus(NesBus::new(rom).unwrap());
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0x8000);
+
+        cpu.run().unwrap();
+        assert_eq!(cpu.register_a, 0x05);
+        assert_eq!(cpu.mem_read(0x10), 0x05);
+    }
+
+    #[test]
+    fn test_prg_rom_ignores_writes() {
+        let mut bus = NesBus::new(test_rom(&[0xEA])).unwrap();
+        bus.write(0x8000, 0x00);
+        assert_eq!(bus.read(0x8000), 0xEA);
+    }
+
+    #[test]
+    fn test_cartridge_writes_reach_the_mapper() {
+        let mut bus = NesBus::new(banked_rom(2, 4, 0)).unwrap();
+        assert_eq!(bus.read(0x8000), 0);
+        bus.write(0xC000, 2);
+        assert_eq!(bus.read(0x8000), 2);
+        assert_eq!(bus.read(0xC000), 3);
+    }
+
+    #[test]
+    fn test_back_to_back_cartridge_writes_are_flagged() {
+        let mut bus = NesBus::new(banked_rom(1, 8, 0)).unwrap();
+        // The second bit of each pair lands on the next cycle and is dropped
+        // by the MMC1, so only the 1s reach the shift register.
+        for _ in 0..5 {
+            bus.read(0x0000);
+            bus.write(0xE000, 1);
+            bus.write(0xE000, 0);
+        }
+        // PRG bank 15 wraps to the last of the eight banks.
+        assert_eq!(bus.read(0x8000), 7);
+    }
+
+    #[test]
+    fn test_ram_is_mirrored_up_to_1fff() {
+        let mut bus = NesBus::new(test_rom(&[])).unwrap();
+        bus.write(0x0012, 0x34);
+        assert_eq!(bus.read(0x0812), 0x34);
+        assert_eq!(bus.read(0x1012), 0x34);
+        assert_eq!(bus.read(0x1812), 0x34);
+
+        bus.write(0x1FFF, 0x56);
+        assert_eq!(bus.read(0x07FF), 0x56);
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut bus = NesBus::new(test_rom(&[])).unwrap();
+        bus.write(0x6000, 0x11);
+        bus.write(0x7FFF, 0x22);
+        assert_eq!(bus.read(0x6000), 0x11);
+        assert_eq!(bus.read(0x7FFF), 0x22);
+        assert_eq!(bus.read(0x0000), 0x00);
+    }
+
+    #[test]
+    fn test_unmapped_reads_return_open_bus() {
+        let mut bus = NesBus::new(test_rom(&[0xEA])).unwrap();
+        assert_eq!(bus.read(0x8000), 0xEA);
+        assert_eq!(bus.read(0x5000), 0xEA);
+        assert_eq!(bus.read(0x4018), 0xEA);
+
+        bus.write(0x5000, 0x42);
+        assert_eq!(bus.read(0x5000), 0x42);
+    }
+
+    #[test]
+    fn test_ppu_registers_are_mirrored_up_to_3fff() {
+        let mut bus = NesBus::new(test_rom(&[])).unwrap();
+        bus.write(0x3FFE, 0x23);
+        bus.write(0x2006, 0x05);
+        bus.write(0x2007, 0x66);
+        assert_eq!(bus.ppu().vram[0x0305], 0x66);
+    }
+
+    #[test]
+    fn test_vblank_nmi_reaches_cpu() {
+        // LDA #$80; STA $2000; loop: JMP loop
+        let mut rom = test_rom(&[0xA9, 0x80, 0x8D, 0x00, 0x20, 0x4C, 0x05, 0x80]);
+        // NMI handler at $9000: LDX #$42; loop: JMP loop
+        rom.prg_rom[0x1000..0x1005].copy_from_slice(&[0xA2, 0x42, 0x4C, 0x02, 0x90]);
+        rom.prg_rom[0x7FFA] = 0x00;
+        rom.prg_rom[0x7FFB] = 0x90;
+
+        let mut cpu = CPU::with_bus(NesBus::new(rom).unwrap());
+        cpu.reset();
+        let result = cpu.run_until(|cpu| cpu.register_x == 0x42).unwrap();
+        assert_eq!(result.mnemonic.to_string(), "LDX");
+        assert_eq!(cpu.bus.ppu().scanline, 241);
+    }
+
+    #[test]
+    fn test_oam_dma_copies_page_into_oam() {
+        // LDA #$03; STA $2003; LDA #$02; STA $4014; BRK
+        let rom = test_rom(&[0xA9, 0x03, 0x8D, 0x03, 0x20, 0xA9, 0x02, 0x8D, 0x14, 0x40, 0x00]);
+        let mut cpu = CPU::with_bus(NesBus::new(rom).unwrap());
+        for i in 0..256u16 {
+            cpu.mem_write(0x0200 + i, i as u8);
+        }
+        cpu.reset();
+        cpu.run().unwrap();
+
+        let oam = &cpu.bus.ppu().oam_data;
+        assert_eq!(oam[3], 0x00);
+        assert_eq!(oam[4], 0x01);
+        assert_eq!(oam[255], 0xFC);
+        assert_eq!(oam[2], 0xFF);
+    }
+
+    #[test]
+    fn test_oam_dma_stalls_cpu() {
+        // LDA #$02; STA $4014; LDA $00; STA $4014; BRK
+        let rom = test_rom(&[0xA9, 0x02, 0x8D, 0x14, 0x40, 0xA5, 0x00, 0x8D, 0x14, 0x40, 0x00]);
+        let mut cpu = CPU::with_bus(NesBus::new(rom).unwrap());
+        cpu.reset();
+
+        cpu.step().unwrap();
+        // Reset took 7 cycles and LDA 2, so the DMA starts on odd cycle 13.
+        assert_eq!(cpu.step().unwrap().cycles, 4 + 514);
+        assert_eq!(cpu.cycles, 527);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.step().unwrap().cycles, 4 + 513);
+        assert_eq!(cpu.cycles, 1047);
+
+        // The PPU kept running through both stalls.
+        let ppu = cpu.bus.ppu();
+        assert_eq!(ppu.scanline as usize * 341 + ppu.dot, (1047 - 7) * 3);
+    }
+
+    #[test]
+    fn test_apu_frame_irq_reaches_cpu() {
+        // CLI; loop: JMP loop
+        let mut rom = test_rom(&[0x58, 0x4C, 0x01, 0x80]);
+        // IRQ handler at $9000: LDA $4015; loop: JMP loop
+        rom.prg_rom[0x1000..0x1006].copy_from_slice(&[0xAD, 0x15, 0x40, 0x4C, 0x03, 0x90]);
+        rom.prg_rom[0x7FFE] = 0x00;
+        rom.prg_rom[0x7FFF] = 0x90;
+
+        let mut cpu = CPU::with_bus(NesBus::new(rom).unwrap());
+        cpu.reset();
+        let result = cpu.run_until(|cpu| cpu.program_counter == 0x9003).unwrap();
+        assert_eq!(result.mnemonic.to_string(), "LDA");
+        assert_eq!(cpu.register_a & 0b0100_0000, 0b0100_0000);
+        assert!(!cpu.bus.irq_line());
+    }
+
+    #[test]
+    fn test_dmc_fetch_reads_cpu_bus_and_stalls_cpu() {
+        // LDA #$0F; STA $4010; LDA #$10; STA $4015; BRK
+        let mut rom = test_rom(&[0xA9, 0x0F, 0x8D, 0x10, 0x40, 0xA9, 0x10, 0x8D, 0x15, 0x40, 0x00]);
+        rom.prg_rom[0x4000] = 0x5A;
+        let mut cpu = CPU::with_bus(NesBus::new(rom).unwrap());
+        cpu.reset();
+
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        // The sample buffer is empty as soon as the DMC is enabled, so the
+        // first byte is fetched right after STA $4015.
+        assert_eq!(cpu.step().unwrap().cycles, 4 + DMC_FETCH_STALL);
+        assert_eq!(cpu.bus.apu().dmc_sample_request(), None);
+    }
+
+    #[test]
+    fn test_audio_is_sampled_at_host_rate() {
+        // Pulse 1: enable, 50% duty at constant volume 15, period $0FE.
+        // LDA #$01; STA $4015; LDA #$BF; STA $4000; LDA #$FE; STA $4002;
+        // LDA #$08; STA $4003; loop: JMP loop
+        let rom = test_rom(&[
+            0xA9, 0x01, 0x8D, 0x15, 0x40, 0xA9, 0xBF, 0x8D, 0x00, 0x40, 0xA9, 0xFE, 0x8D, 0x02,
+            0x40, 0xA9, 0x08, 0x8D, 0x03, 0x40, 0x4C, 0x14, 0x80,
+        ]);
+        let mut cpu = CPU::with_bus(NesBus::new(rom).unwrap());
+        cpu.reset();
+        assert!(cpu.bus.take_audio_samples().is_empty());
+
+        cpu.bus.enable_audio(48_000);
+        cpu.run_for_cycles(CPU_CLOCK_NTSC as usize / 10).unwrap();
+
+        let samples = cpu.bus.take_audio_samples();
+        assert!((4_790..=4_810).contains(&samples.len()));
+        // A ~440Hz square wave swings well away from zero both ways.
+        let max = samples.iter().cloned().fold(f32::MIN, f32::max);
+        let min = samples.iter().cloned().fold(f32::MAX, f32::min);
+        assert!(max > 0.05 && min < -0.05);
+
+        assert!(cpu.bus.take_audio_samples().is_empty());
+    }
+
+    #[test]
+    fn test_joypads_read_through_cpu_with_open_bus_upper_bits() {
+        // LDA #$01; STA $4016; LDA #$00; STA $4016;
+        // LDA $4016; LDX $4017; LDY $4017; BRK
+        let rom = test_rom(&[
+            0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, 0xAD, 0x16, 0x40, 0xAE,
+            0x17, 0x40, 0xAC, 0x17, 0x40, 0x00,
+        ]);
+        let mut cpu = CPU::with_bus(NesBus::new(rom).unwrap());
+        cpu.bus.joypad1_mut().set_buttons(JoypadButton::BUTTON_A);
+        cpu.bus.joypad2_mut().set_buttons(JoypadButton::BUTTON_B);
+        cpu.reset();
+        cpu.run().unwrap();
+
+        // The last value on the bus was the $40 high byte of the address.
+        assert_eq!(cpu.register_a, 0x41);
+        assert_eq!(cpu.register_x, 0x40);
+        assert_eq!(cpu.register_y, 0x41);
+    }
+}
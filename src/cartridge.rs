@@ -0,0 +1,533 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const NES_TAG: [u8; 4] = [b'N', b'E', b'S', 0x1A];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+pub const PRG_ROM_PAGE_SIZE: usize = 16384;
+pub const CHR_ROM_PAGE_SIZE: usize = 8192;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    FourScreen,
+    /// All four nametables show the first 1KB of VRAM. Selected by mappers.
+    SingleScreenLower,
+    /// All four nametables show the second 1KB of VRAM. Selected by mappers.
+    SingleScreenUpper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomFormat {
+    INes,
+    Nes20,
+}
+
+/// CPU/PPU timing the image was made for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimingMode {
+    Ntsc,
+    Pal,
+    /// Runs on both NTSC and PAL consoles.
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    /// NES 2.0 extended console type from header byte 13.
+    Extended(u8),
+}
+
+/// Everything the header says about the cartridge. Sizes are in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomInfo {
+    pub format: RomFormat,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub screen_mirroring: Mirroring,
+    /// The cartridge keeps PRG-RAM alive with a battery.
+    pub battery: bool,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: TimingMode,
+    pub console_type: ConsoleType,
+}
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    /// The file does not start with "NES\x1A".
+    NotINes,
+    /// The header declares no PRG-ROM at all.
+    NoPrgRom,
+    /// The file ends before the trainer, PRG or CHR data the header declares.
+    Truncated { expected: usize, actual: usize },
+    /// The NES 2.0 header declares PRG and CHR sizes that together do not
+    /// fit in memory, so no file could hold them.
+    SizeOverflow { prg_rom_size: usize, chr_rom_size: usize },
+    /// The header names a mapper this emulator does not implement.
+    UnsupportedMapper(u16),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::Io(err) => write!(f, "could not read rom: {}", err),
+            RomError::NotINes => write!(f, "file is not in iNES format"),
+            RomError::NoPrgRom => write!(f, "header declares zero PRG-ROM banks"),
+            RomError::Truncated { expected, actual } => write!(
+                f,
+                "rom is truncated: header needs {} bytes, file has {}",
+                expected, actual
+            ),
+            RomError::SizeOverflow {
+                prg_rom_size,
+                chr_rom_size,
+            } => write!(
+                f,
+                "header declares {} bytes of PRG-ROM and {} bytes of CHR-ROM, more than can be addressed",
+                prg_rom_size, chr_rom_size
+            ),
+            RomError::UnsupportedMapper(mapper) => write!(f, "mapper {} is not supported", mapper),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> Self {
+        RomError::Io(err)
+    }
+}
+
+/// A parsed iNES or NES 2.0 image.
+///
+/// Header layout (https://wiki.nesdev.com/w/index.php/NES_2.0):
+///
+///  0-3  "NES" followed by MS-DOS end-of-file ($1A)
+///  4    PRG-ROM size LSB, in 16KB units
+///  5    CHR-ROM size LSB, in 8KB units (0 means the board uses CHR-RAM)
+///  6    Flags 6: mapper D0..D3, four-screen, trainer, battery, mirroring
+///  7    Flags 7: mapper D4..D7, NES 2.0 identifier, console type
+///  8    NES 2.0: mapper D8..D11 and submapper. iNES: PRG-RAM in 8KB units
+///  9    NES 2.0: PRG-ROM and CHR-ROM size MSB nibbles
+///  10   NES 2.0: PRG-RAM and PRG-NVRAM shift counts
+///  11   NES 2.0: CHR-RAM and CHR-NVRAM shift counts
+///  12   NES 2.0: CPU/PPU timing
+///  13   NES 2.0: Vs. System type or extended console type
+///  14-15 NES 2.0: misc ROMs and default expansion device, ignored here
+pub struct Rom {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    /// 512 bytes meant for $7000-$71FF, present on some old dumps.
+    pub trainer: Option<Vec<u8>>,
+    pub info: RomInfo,
+}
+
+impl Rom {
+    pub fn new(raw: &[u8]) -> Result<Rom, RomError> {
+        if raw.len() < HEADER_SIZE || raw[0..4] != NES_TAG {
+            return Err(RomError::NotINes);
+        }
+
+        let info = match (raw[7] >> 2) & 0b11 {
+            0b10 => parse_nes2_header(raw),
+            0b00 => parse_ines_header(raw, true),
+            // Archaic dumps often have text like "DiskDude!" from byte 7
+            // on, so only flags 6 can be trusted.
+            _ => parse_ines_header(raw, false),
+        };
+
+        if info.prg_rom_size == 0 {
+            return Err(RomError::NoPrgRom);
+        }
+
+        let has_trainer = raw[6] & 0b100 != 0;
+
+        let trainer_start = HEADER_SIZE;
+        let prg_rom_start = trainer_start + if has_trainer { TRAINER_SIZE } else { 0 };
+        // Exponent-form NES 2.0 sizes can come close to usize::MAX, so the
+        // sum can overflow before it is ever compared with the file length.
+        let (chr_rom_start, expected) = prg_rom_start
+            .checked_add(info.prg_rom_size)
+            .and_then(|chr_rom_start| {
+                let expected = chr_rom_start.checked_add(info.chr_rom_size)?;
+                Some((chr_rom_start, expected))
+            })
+            .ok_or(RomError::SizeOverflow {
+                prg_rom_size: info.prg_rom_size,
+                chr_rom_size: info.chr_rom_size,
+            })?;
+        if raw.len() < expected {
+            return Err(RomError::Truncated {
+                expected,
+                actual: raw.len(),
+            });
+        }
+
+        Ok(Rom {
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom: raw[chr_rom_start..expected].to_vec(),
+            trainer: if has_trainer {
+                Some(raw[trainer_start..prg_rom_start].to_vec())
+            } else {
+                None
+            },
+            info,
+        })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Rom, RomError> {
+        let raw = fs::read(path)?;
+        Rom::new(&raw)
+    }
+}
+
+fn screen_mirroring(flags_6: u8) -> Mirroring {
+    let four_screen = flags_6 & 0b1000 != 0;
+    let vertical_mirroring = flags_6 & 0b1 != 0;
+    match (four_screen, vertical_mirroring) {
+        (true, _) => Mirroring::FourScreen,
+        (false, true) => Mirroring::Vertical,
+        (false, false) => Mirroring::Horizontal,
+    }
+}
+
+fn parse_ines_header(raw: &[u8], trust_bytes_7_to_15: bool) -> RomInfo {
+    let (flags_7, prg_ram_banks) = if trust_bytes_7_to_15 {
+        (raw[7], raw[8])
+    } else {
+        (0, 0)
+    };
+    let battery = raw[6] & 0b10 != 0;
+    let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+    // Byte 8 is often zero in dumps; zero still means one 8KB bank.
+    let prg_ram_size = prg_ram_banks.max(1) as usize * 8192;
+
+    RomInfo {
+        format: RomFormat::INes,
+        mapper: ((flags_7 & 0b1111_0000) | (raw[6] >> 4)) as u16,
+        submapper: 0,
+        screen_mirroring: screen_mirroring(raw[6]),
+        battery,
+        prg_rom_size: raw[4] as usize * PRG_ROM_PAGE_SIZE,
+        chr_rom_size,
+        prg_ram_size: if battery { 0 } else { prg_ram_size },
+        prg_nvram_size: if battery { prg_ram_size } else { 0 },
+        chr_ram_size: if chr_rom_size == 0 { CHR_ROM_PAGE_SIZE } else { 0 },
+        chr_nvram_size: 0,
+        timing: TimingMode::Ntsc,
+        console_type: match flags_7 & 0b11 {
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Nes,
+        },
+    }
+}
+
+fn parse_nes2_header(raw: &[u8]) -> RomInfo {
+    RomInfo {
+        format: RomFormat::Nes20,
+        mapper: ((raw[8] & 0b1111) as u16) << 8
+            | (raw[7] & 0b1111_0000) as u16
+            | (raw[6] >> 4) as u16,
+        submapper: raw[8] >> 4,
+        screen_mirroring: screen_mirroring(raw[6]),
+        battery: raw[6] & 0b10 != 0,
+        prg_rom_size: nes2_rom_size(raw[4], raw[9] & 0b1111, PRG_ROM_PAGE_SIZE),
+        chr_rom_size: nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE),
+        prg_ram_size: nes2_ram_size(raw[10] & 0b1111),
+        prg_nvram_size: nes2_ram_size(raw[10] >> 4),
+        chr_ram_size: nes2_ram_size(raw[11] & 0b1111),
+        chr_nvram_size: nes2_ram_size(raw[11] >> 4),
+        timing: match raw[12] & 0b11 {
+            0 => TimingMode::Ntsc,
+            1 => TimingMode::Pal,
+            2 => TimingMode::MultiRegion,
+            _ => TimingMode::Dendy,
+        },
+        console_type: match raw[7] & 0b11 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(raw[13] & 0b1111),
+        },
+    }
+}
+
+/// NES 2.0 ROM sizes are a 12-bit bank count, unless the MSB nibble is $F,
+/// in which case the LSB byte is EEEEEEMM and the size is 2^E * (MM*2+1).
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> usize {
+    if msb == 0b1111 {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        2usize.saturating_pow(exponent).saturating_mul(multiplier)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * page_size
+    }
+}
+
+/// RAM sizes are shift counts: 0 means none, otherwise 64 << shift bytes.
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    pub struct TestRom {
+        pub header: Vec<u8>,
+        pub trainer: Option<Vec<u8>>,
+        pub prg_rom: Vec<u8>,
+        pub chr_rom: Vec<u8>,
+    }
+
+    pub fn create_rom(rom: TestRom) -> Vec<u8> {
+        let mut result = Vec::with_capacity(
+            rom.header.len()
+                + rom.trainer.as_ref().map_or(0, |t| t.len())
+                + rom.prg_rom.len()
+                + rom.chr_rom.len(),
+        );
+
+        result.extend(&rom.header);
+        if let Some(t) = rom.trainer {
+            result.extend(t);
+        }
+        result.extend(&rom.prg_rom);
+        result.extend(&rom.chr_rom);
+
+        result
+    }
+
+    /// A 32KB NROM image whose PRG is `program` at $8000 with the reset
+    /// vector pointing at it.
+    pub fn test_rom(program: &[u8]) -> Rom {
+        let mut prg_rom = vec![0; 2 * PRG_ROM_PAGE_SIZE];
+        prg_rom[..program.len()].copy_from_slice(program);
+        prg_rom[0x7FFC] = 0x00;
+        prg_rom[0x7FFD] = 0x80;
+
+        let raw = create_rom(TestRom {
+            header: vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x00, 0x00, 00, 00, 00, 00, 00, 00, 00, 00],
+            trainer: None,
+            prg_rom,
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        Rom::new(&raw).unwrap()
+    }
+
+    #[test]
+    fn test() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom: Rom = Rom::new(&test_rom).unwrap();
+
+        assert_eq!(rom.chr_rom, vec!(2; CHR_ROM_PAGE_SIZE));
+        assert_eq!(rom.prg_rom, vec!(1; 2 * PRG_ROM_PAGE_SIZE));
+        assert_eq!(rom.info.format, RomFormat::INes);
+        assert_eq!(rom.info.mapper, 3);
+        assert_eq!(rom.info.screen_mirroring, Mirroring::Vertical);
+        assert!(!rom.info.battery);
+        assert_eq!(rom.info.prg_ram_size, 8192);
+        assert_eq!(rom.info.chr_ram_size, 0);
+        assert!(rom.trainer.is_none());
+    }
+
+    #[test]
+    fn test_with_trainer_and_battery() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x30 | 0b110, 0x20, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: Some(vec![0; TRAINER_SIZE]),
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom: Rom = Rom::new(&test_rom).unwrap();
+
+        assert_eq!(rom.chr_rom, vec!(2; CHR_ROM_PAGE_SIZE));
+        assert_eq!(rom.prg_rom, vec!(1; 2 * PRG_ROM_PAGE_SIZE));
+        assert_eq!(rom.info.mapper, 0x23);
+        assert_eq!(rom.info.screen_mirroring, Mirroring::Horizontal);
+        assert!(rom.info.battery);
+        assert_eq!(rom.info.prg_nvram_size, 8192);
+        assert_eq!(rom.info.prg_ram_size, 0);
+        assert_eq!(rom.trainer.unwrap().len(), TRAINER_SIZE);
+    }
+
+    #[test]
+    fn test_four_screen_overrides_mirroring_bit() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0b1001, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+
+        let rom: Rom = Rom::new(&test_rom).unwrap();
+        assert_eq!(rom.info.screen_mirroring, Mirroring::FourScreen);
+        assert!(rom.chr_rom.is_empty());
+        assert_eq!(rom.info.chr_ram_size, CHR_ROM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_invalid_headers() {
+        assert!(matches!(Rom::new(&[0x4E, 0x45, 0x53]), Err(RomError::NotINes)));
+        assert!(matches!(
+            Rom::new(&[0x4E, 0x45, 0x53, 0x00, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            Err(RomError::NotINes)
+        ));
+        assert!(matches!(
+            Rom::new(&[0x4E, 0x45, 0x53, 0x1A, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            Err(RomError::NoPrgRom)
+        ));
+    }
+
+    #[test]
+    fn test_truncated_rom() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x00, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+
+        match Rom::new(&test_rom) {
+            Err(err @ RomError::Truncated { .. }) => assert_eq!(
+                err.to_string(),
+                "rom is truncated: header needs 40976 bytes, file has 16400"
+            ),
+            _ => panic!("expected a truncated rom error"),
+        }
+    }
+
+    #[test]
+    fn test_nes2_header() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x12, 0x48, 0x31, 0x00, 0x70, 0x07, 0x01, 0x00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&test_rom).unwrap();
+        assert_eq!(rom.info.format, RomFormat::Nes20);
+        assert_eq!(rom.info.mapper, 0x141);
+        assert_eq!(rom.info.submapper, 3);
+        assert_eq!(rom.info.screen_mirroring, Mirroring::Horizontal);
+        assert!(rom.info.battery);
+        assert_eq!(rom.info.prg_rom_size, PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.info.chr_rom_size, CHR_ROM_PAGE_SIZE);
+        assert_eq!(rom.info.prg_ram_size, 0);
+        assert_eq!(rom.info.prg_nvram_size, 8192);
+        assert_eq!(rom.info.chr_ram_size, 8192);
+        assert_eq!(rom.info.chr_nvram_size, 0);
+        assert_eq!(rom.info.timing, TimingMode::Pal);
+        assert_eq!(rom.info.console_type, ConsoleType::Nes);
+    }
+
+    #[test]
+    fn test_nes2_console_and_timing() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x00, 0x0B, 0x00, 0x00, 0x00, 0x00, 0x03, 0x05, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+
+        let rom = Rom::new(&test_rom).unwrap();
+        assert_eq!(rom.info.timing, TimingMode::Dendy);
+        assert_eq!(rom.info.console_type, ConsoleType::Extended(5));
+    }
+
+    #[test]
+    fn test_archaic_header_ignores_byte_7() {
+        let mut header = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x10];
+        header.extend(b"DiskDude!");
+        let test_rom = create_rom(TestRom {
+            header,
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&test_rom).unwrap();
+        assert_eq!(rom.info.format, RomFormat::INes);
+        assert_eq!(rom.info.mapper, 1);
+        assert_eq!(rom.info.prg_ram_size, 8192);
+    }
+
+    #[test]
+    fn test_nes2_rom_sizes() {
+        assert_eq!(nes2_rom_size(0x02, 0x0, PRG_ROM_PAGE_SIZE), 2 * PRG_ROM_PAGE_SIZE);
+        assert_eq!(nes2_rom_size(0x00, 0x1, PRG_ROM_PAGE_SIZE), 256 * PRG_ROM_PAGE_SIZE);
+        // 2^14 * 3 = 48KB
+        assert_eq!(nes2_rom_size(0b0011_1001, 0xF, PRG_ROM_PAGE_SIZE), 49152);
+        assert_eq!(nes2_ram_size(0), 0);
+        assert_eq!(nes2_ram_size(7), 8192);
+    }
+
+    #[test]
+    fn test_nes2_oversized_rom_is_size_overflow() {
+        // Exponent 63, multiplier 7 saturates to usize::MAX, once for PRG
+        // (byte 9 bits 0-3) and once for CHR (bits 4-7).
+        for (prg_lsb, chr_lsb, msb) in [(0xFF, 0x00, 0x0F), (0x01, 0xFF, 0xF0)] {
+            let test_rom = create_rom(TestRom {
+                header: vec![
+                    0x4E, 0x45, 0x53, 0x1A, prg_lsb, chr_lsb, 0x00, 0x08, 00, msb, 00, 00, 00, 00, 00, 00,
+                ],
+                trainer: None,
+                prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+                chr_rom: vec![],
+            });
+
+            match Rom::new(&test_rom) {
+                Err(
+                    err @ RomError::SizeOverflow {
+                        prg_rom_size,
+                        chr_rom_size,
+                    },
+                ) => {
+                    assert!(prg_rom_size == usize::MAX || chr_rom_size == usize::MAX);
+                    assert!(err.to_string().contains("more than can be addressed"));
+                }
+                _ => panic!("expected a size overflow error"),
+            }
+        }
+    }
+}
@@ -1,3 +1,4 @@
+#[cfg(test)]
 mod cpu_constants {
     pub const LDA_IMMEDIATE: u8 = 0xA9;
     pub const LDA_ZP: u8 = 0xA5;
@@ -8,28 +9,208 @@ mod cpu_constants {
     pub const LDA_INDX: u8 = 0xA1;
     pub const LDA_INDY: u8 = 0xB1;
 
+    pub const LDX_IMMEDIATE: u8 = 0xA2;
+    pub const LDY_IMMEDIATE: u8 = 0xA0;
+
     pub const STA_ZP: u8 = 0x85;
+    pub const STA_ABSX: u8 = 0x9D;
+    pub const STX_ZP: u8 = 0x86;
+
+    pub const ADC_IMMEDIATE: u8 = 0x69;
+    pub const SBC_IMMEDIATE: u8 = 0xE9;
+    pub const AND_IMMEDIATE: u8 = 0x29;
+    pub const ORA_IMMEDIATE: u8 = 0x09;
+    pub const EOR_IMMEDIATE: u8 = 0x49;
+    pub const CMP_IMMEDIATE: u8 = 0xC9;
+    pub const CPX_IMMEDIATE: u8 = 0xE0;
+    pub const BIT_ZP: u8 = 0x24;
+
+    pub const ASL_ACC: u8 = 0x0A;
+    pub const LSR_ACC: u8 = 0x4A;
+    pub const ROL_ACC: u8 = 0x2A;
+    pub const ROR_ACC: u8 = 0x6A;
+    pub const INC_ZP: u8 = 0xE6;
+    pub const INC_ABS: u8 = 0xEE;
+    pub const DEC_ZP: u8 = 0xC6;
 
+    pub const BNE: u8 = 0xD0;
+    pub const BEQ: u8 = 0xF0;
+    pub const JMP_ABS: u8 = 0x4C;
+    pub const JMP_IND: u8 = 0x6C;
+    pub const JSR: u8 = 0x20;
+    pub const RTS: u8 = 0x60;
+    pub const RTI: u8 = 0x40;
+    pub const CLI: u8 = 0x58;
+
+    pub const PHA: u8 = 0x48;
+    pub const PLA: u8 = 0x68;
+    pub const PHP: u8 = 0x08;
+    pub const PLP: u8 = 0x28;
+
+    pub const SEC: u8 = 0x38;
+    pub const CLC: u8 = 0x18;
+
+    pub const TSX: u8 = 0xBA;
+    pub const TXS: u8 = 0x9A;
     pub const TAX: u8 = 0xAA;
     pub const TAY: u8 = 0xA8;
     pub const INX: u8 = 0xE8;
+    pub const DEX: u8 = 0xCA;
     pub const BRK: u8 = 0x00;
-}
+    pub const KIL: u8 = 0x02;
 
+    pub const LAX_ZP: u8 = 0xA7;
+    pub const SAX_ZP: u8 = 0x87;
+    pub const DCP_ZP: u8 = 0xC7;
+    pub const ISB_ZP: u8 = 0xE7;
+    pub const SLO_ZP: u8 = 0x07;
+    pub const RLA_ZP: u8 = 0x27;
+    pub const SRE_ZP: u8 = 0x47;
+    pub const RRA_ZP: u8 = 0x67;
+    pub const ANC_IMMEDIATE: u8 = 0x0B;
+    pub const ALR_IMMEDIATE: u8 = 0x4B;
+    pub const ARR_IMMEDIATE: u8 = 0x6B;
+    pub const AXS_IMMEDIATE: u8 = 0xCB;
+    pub const SBC_IMMEDIATE_UNOFFICIAL: u8 = 0xEB;
+    pub const NOP_ABSX: u8 = 0x1C;
+}
 
+#[allow(clippy::module_inception)]
 pub mod cpu {
+    use std::fmt;
+
+    use crate::bus::{Bus, FlatMemory};
+
+    const STACK: u16 = 0x0100;
+    const STACK_RESET: u8 = 0xfd;
+
+    const NMI_VECTOR: u16 = 0xFFFA;
+    const RESET_VECTOR: u16 = 0xFFFC;
+    const IRQ_BRK_VECTOR: u16 = 0xFFFE;
+
+    bitflags! {
+        /// # Status Register (P) http://wiki.nesdev.com/w/index.php/Status_flags
+        ///
+        ///  7 6 5 4 3 2 1 0
+        ///  N V _ B D I Z C
+        ///  | |   | | | | +--- Carry Flag
+        ///  | |   | | | +----- Zero Flag
+        ///  | |   | | +------- Interrupt Disable
+        ///  | |   | +--------- Decimal Mode (not used on NES)
+        ///  | |   +----------- Break Command
+        ///  | +--------------- Overflow Flag
+        ///  +----------------- Negative Flag
+        ///
+        /// B has no storage in the real register; it only exists on the copy
+        /// of the flags pushed to the stack, and `status` never holds it.
+        /// Bit 5 always reads back as 1, so `status` keeps UNUSED set.
+        pub struct CpuFlags: u8 {
+            const CARRY             = 0b0000_0001;
+            const ZERO              = 0b0000_0010;
+            const INTERRUPT_DISABLE = 0b0000_0100;
+            const DECIMAL           = 0b0000_1000;
+            const BREAK             = 0b0001_0000;
+            const UNUSED            = 0b0010_0000;
+            const OVERFLOW          = 0b0100_0000;
+            const NEGATIVE          = 0b1000_0000;
+        }
+    }
+
+    impl CpuFlags {
+        /// Flags the 6502 comes out of reset with.
+        pub fn power_on() -> Self {
+            CpuFlags::INTERRUPT_DISABLE | CpuFlags::UNUSED
+        }
+
+        /// The byte written to the stack. Bit 5 is always set, B is set only
+        /// when the push comes from PHP or BRK rather than a hardware interrupt.
+        pub fn to_pushed_byte(self, from_software: bool) -> u8 {
+            let mut flags = self | CpuFlags::UNUSED;
+            flags.set(CpuFlags::BREAK, from_software);
+            flags.bits()
+        }
+
+        /// Restores flags pulled by PLP or RTI, which ignore B and bit 5.
+        pub fn from_pulled_byte(data: u8) -> Self {
+            let mut flags = CpuFlags::from_bits_truncate(data);
+            flags.remove(CpuFlags::BREAK);
+            flags.insert(CpuFlags::UNUSED);
+            flags
+        }
+    }
+
+    /// `interrupt` is set when the step serviced an NMI or IRQ and the
+    /// handler's first byte was the one that failed.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum CpuError {
+        /// The byte at `pc` does not decode to any known instruction.
+        UnknownOpcode { pc: u16, opcode: u8, interrupt: Option<Interrupt> },
+        /// An instruction tried to resolve an operand in a mode it has none for.
+        InvalidAddressingMode { pc: u16, mode: AddressingMode },
+        /// The CPU hit a KIL/JAM and stays locked until the next reset.
+        Jammed { pc: u16, opcode: u8, interrupt: Option<Interrupt> },
+    }
+
+    impl fmt::Display for CpuError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                CpuError::UnknownOpcode { pc, opcode, interrupt } => {
+                    write!(f, "unknown opcode ${:02X} at ${:04X}", opcode, pc)?;
+                    fmt_interrupt(f, *interrupt)
+                }
+                CpuError::InvalidAddressingMode { pc, mode } => {
+                    write!(f, "invalid addressing mode {:?} at ${:04X}", mode, pc)
+                }
+                CpuError::Jammed { pc, opcode, interrupt } => {
+                    write!(f, "cpu jammed by ${:02X} at ${:04X}", opcode, pc)?;
+                    fmt_interrupt(f, *interrupt)
+                }
+            }
+        }
+    }
+
+    fn fmt_interrupt(f: &mut fmt::Formatter, interrupt: Option<Interrupt>) -> fmt::Result {
+        match interrupt {
+            Some(interrupt) => write!(f, " entering the {:?} handler", interrupt),
+            None => Ok(()),
+        }
+    }
 
+    impl std::error::Error for CpuError {}
+
+    /// What `CPU::step` does when it fetches a byte that is not an opcode.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum UnknownOpcodePolicy {
+        /// Stop and return `CpuError::UnknownOpcode`.
+        Halt,
+        /// Skip the byte as a one-byte, two-cycle NOP.
+        Nop,
+        /// Lock up like the real KIL/JAM opcodes until `CPU::reset`.
+        Jam,
+    }
 
-    pub struct CPU {
+    pub struct CPU<B: Bus = FlatMemory> {
         pub register_a: u8,
-        pub status: u8,
+        pub status: CpuFlags,
         pub program_counter: u16,
 
         pub register_x: u8,
         pub register_y: u8,
-        memory: [u8; 0xFFFF]
+        pub stack_pointer: u8,
+        /// Total CPU cycles elapsed since reset.
+        pub cycles: usize,
+        pub unknown_opcode_policy: UnknownOpcodePolicy,
+        pub brk_behavior: BrkBehavior,
+        jammed: bool,
+        /// NMI level driven through `set_nmi_line`.
+        nmi_input: bool,
+        /// Combined NMI level as of the last sample, for edge detection.
+        nmi_line: bool,
+        nmi_pending: bool,
+        irq_line: bool,
+        pub bus: B,
     }
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     #[allow(non_camel_case_types)]
     pub enum AddressingMode {
         Immediate,
@@ -43,390 +224,2334 @@ pub mod cpu {
         Indirect_Y,
         NoneAddressing,
     }
-    
+
+    /// What a single call to `CPU::step` executed.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct StepResult {
+        pub opcode: u8,
+        pub mnemonic: Mnemonic,
+        pub mode: AddressingMode,
+        /// Effective address of the operand, if the addressing mode has one.
+        pub operand_address: Option<u16>,
+        /// Cycles consumed, including page-crossing and branch penalties and
+        /// any DMA stall the instruction triggered.
+        pub cycles: usize,
+        /// True when the instruction was BRK and execution stopped.
+        pub halted: bool,
+        /// Hardware interrupt serviced before the instruction, if any.
+        pub interrupt: Option<Interrupt>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Interrupt {
+        NMI,
+        IRQ,
+    }
+
+    impl Interrupt {
+        pub fn vector(self) -> u16 {
+            match self {
+                Interrupt::NMI => NMI_VECTOR,
+                Interrupt::IRQ => IRQ_BRK_VECTOR,
+            }
+        }
+    }
+
+    /// How BRK is executed.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum BrkBehavior {
+        /// Stop execution, so test programs can end with BRK.
+        Halt,
+        /// Push PC + 2 and status with B set, then jump through $FFFE.
+        Interrupt,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Mnemonic {
+        ADC, ALR, ANC, AND, ARR, ASL, AXS, BCC,
+        BCS, BEQ, BIT, BMI, BNE, BPL, BRK, BVC,
+        BVS, CLC, CLD, CLI, CLV, CMP, CPX, CPY,
+        DCP, DEC, DEX, DEY, EOR, INC, INX, INY,
+        ISB, JMP, JSR, LAX, LDA, LDX, LDY, LSR,
+        NOP, ORA, PHA, PHP, PLA, PLP, RLA, ROL,
+        ROR, RRA, RTI, RTS, SAX, SBC, SEC, SED,
+        SEI, SLO, SRE, STA, STX, STY, TAX, TAY,
+        TSX, TXA, TXS, TYA,
+    }
+
+    impl fmt::Display for Mnemonic {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{:?}", self)
+        }
+    }
+
     pub struct OpCode {
         pub opcode: u8,
-        pub name: &'static str,
+        pub mnemonic: Mnemonic,
         pub takes_bytes: u16,
         pub takes_cycles: u16,
-        pub adressing_mode: AddressingMode
+        pub adressing_mode: AddressingMode,
+        /// Undocumented opcode; works on real hardware but tools may warn.
+        pub unofficial: bool,
     }
 
     impl OpCode {
-        pub fn new(
+        pub const fn new(
+            opcode: u8,
+            mnemonic: Mnemonic,
+            takes_bytes: u16,
+            takes_cycles: u16,
+            adressing_mode: AddressingMode
+        ) -> Self {
+            OpCode {
+                opcode,
+                mnemonic,
+                takes_bytes,
+                takes_cycles,
+                adressing_mode,
+                unofficial: false,
+            }
+        }
+
+        pub const fn unofficial(
             opcode: u8,
-            name: &'static str,
+            mnemonic: Mnemonic,
             takes_bytes: u16,
             takes_cycles: u16,
             adressing_mode: AddressingMode
         ) -> Self {
             OpCode {
-                opcode: opcode,
-                name: name,
-                takes_bytes: takes_bytes,
-                takes_cycles: takes_cycles,
-                adressing_mode: adressing_mode,
+                unofficial: true,
+                ..OpCode::new(opcode, mnemonic, takes_bytes, takes_cycles, adressing_mode)
             }
         }
     }
 
-    lazy_static! {
-        pub static ref OPCODES: Vec<OpCode> = vec![
-            // LDA
-            OpCode::new(0xA9, "LDA", 2,2,AddressingMode::Immediate),
-            OpCode::new(0xA5, "LDA", 2,3,AddressingMode::ZeroPage),
-            OpCode::new(0xB5, "LDA", 2,4,AddressingMode::ZeroPage_X),
-            OpCode::new(0xAD, "LDA", 2,4,AddressingMode::Absolute),
-            OpCode::new(0xBD, "LDA", 2,4,AddressingMode::Absolute_X),
-            OpCode::new(0xB9, "LDA", 2,4,AddressingMode::Absolute_Y),
-            OpCode::new(0xA1, "LDA", 2,6,AddressingMode::Indirect_X),
-            OpCode::new(0xB1, "LDA", 2,5,AddressingMode::Indirect_Y),
-
-            // STA
-            OpCode::new(0x85, "STA", 2,3,AddressingMode::ZeroPage),
-            OpCode::new(0x95, "STA", 2,4,AddressingMode::ZeroPage_X),
-            OpCode::new(0x8D, "STA", 3,4,AddressingMode::Absolute),
-            OpCode::new(0x9D, "STA", 3,5,AddressingMode::Absolute_X),
-            OpCode::new(0x99, "STA", 3,5,AddressingMode::Absolute_Y),
-            OpCode::new(0x81, "STA", 2,6,AddressingMode::Indirect_X),
-            OpCode::new(0x91, "STA", 2,6,AddressingMode::Indirect_Y),
-
-            // TAX
-            OpCode::new(0xAA, "TAX", 1,2,AddressingMode::NoneAddressing),
-            // TAY
-            OpCode::new(0xA8, "TAY", 1,2,AddressingMode::NoneAddressing),
-            
-            //INX
-            OpCode::new(0xE8, "INX", 1,2,AddressingMode::NoneAddressing),
-            //BRK
-            OpCode::new(0x00, "BRK", 1,7,AddressingMode::NoneAddressing),
-            
-        ];
+    /// How an instruction left the program counter.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Flow {
+        /// Move on past the operand bytes.
+        Next,
+        /// The instruction loaded the PC itself.
+        Jump,
+        /// BRK under `BrkBehavior::Halt`.
+        Halt,
     }
-    
-    pub fn find_opcode_by_instruction(instruction: u8) -> Option<&'static OpCode> {
-        for opcode in OPCODES.iter() {
-            if opcode.opcode == instruction {
-                return Some(opcode);
-            }
+
+    /// How `CPU::step` runs one opcode, picked per table entry.
+    enum Handler<B: Bus> {
+        /// Implied and accumulator instructions.
+        Implied(fn(&mut CPU<B>)),
+        /// Instructions that work on their operand's effective address.
+        Operand(fn(&mut CPU<B>, (u16, bool))),
+        /// Branches, JMP (indirect), RTS, RTI and BRK, which read what they
+        /// need themselves and report where execution goes next.
+        Control(fn(&mut CPU<B>) -> Flow),
+        /// JMP and JSR to an absolute target.
+        Jump(fn(&mut CPU<B>, u16)),
+    }
+
+    // Derived impls would require `B: Copy`.
+    impl<B: Bus> Clone for Handler<B> {
+        fn clone(&self) -> Self {
+            *self
+        }
+    }
+
+    impl<B: Bus> Copy for Handler<B> {}
+
+    type Decoded<B> = (&'static OpCode, Handler<B>);
+
+    pub static OPCODES: [OpCode; 236] = [
+        //BRK
+        OpCode::new(0x00, Mnemonic::BRK, 1,7,AddressingMode::NoneAddressing),
+        //NOP
+        OpCode::new(0xEA, Mnemonic::NOP, 1,2,AddressingMode::NoneAddressing),
+
+        // Arithmetic
+        OpCode::new(0x69, Mnemonic::ADC, 2,2,AddressingMode::Immediate),
+        OpCode::new(0x65, Mnemonic::ADC, 2,3,AddressingMode::ZeroPage),
+        OpCode::new(0x75, Mnemonic::ADC, 2,4,AddressingMode::ZeroPage_X),
+        OpCode::new(0x6D, Mnemonic::ADC, 3,4,AddressingMode::Absolute),
+        OpCode::new(0x7D, Mnemonic::ADC, 3,4,AddressingMode::Absolute_X),
+        OpCode::new(0x79, Mnemonic::ADC, 3,4,AddressingMode::Absolute_Y),
+        OpCode::new(0x61, Mnemonic::ADC, 2,6,AddressingMode::Indirect_X),
+        OpCode::new(0x71, Mnemonic::ADC, 2,5,AddressingMode::Indirect_Y),
+
+        OpCode::new(0xE9, Mnemonic::SBC, 2,2,AddressingMode::Immediate),
+        OpCode::new(0xE5, Mnemonic::SBC, 2,3,AddressingMode::ZeroPage),
+        OpCode::new(0xF5, Mnemonic::SBC, 2,4,AddressingMode::ZeroPage_X),
+        OpCode::new(0xED, Mnemonic::SBC, 3,4,AddressingMode::Absolute),
+        OpCode::new(0xFD, Mnemonic::SBC, 3,4,AddressingMode::Absolute_X),
+        OpCode::new(0xF9, Mnemonic::SBC, 3,4,AddressingMode::Absolute_Y),
+        OpCode::new(0xE1, Mnemonic::SBC, 2,6,AddressingMode::Indirect_X),
+        OpCode::new(0xF1, Mnemonic::SBC, 2,5,AddressingMode::Indirect_Y),
+
+        OpCode::new(0x29, Mnemonic::AND, 2,2,AddressingMode::Immediate),
+        OpCode::new(0x25, Mnemonic::AND, 2,3,AddressingMode::ZeroPage),
+        OpCode::new(0x35, Mnemonic::AND, 2,4,AddressingMode::ZeroPage_X),
+        OpCode::new(0x2D, Mnemonic::AND, 3,4,AddressingMode::Absolute),
+        OpCode::new(0x3D, Mnemonic::AND, 3,4,AddressingMode::Absolute_X),
+        OpCode::new(0x39, Mnemonic::AND, 3,4,AddressingMode::Absolute_Y),
+        OpCode::new(0x21, Mnemonic::AND, 2,6,AddressingMode::Indirect_X),
+        OpCode::new(0x31, Mnemonic::AND, 2,5,AddressingMode::Indirect_Y),
+
+        OpCode::new(0x49, Mnemonic::EOR, 2,2,AddressingMode::Immediate),
+        OpCode::new(0x45, Mnemonic::EOR, 2,3,AddressingMode::ZeroPage),
+        OpCode::new(0x55, Mnemonic::EOR, 2,4,AddressingMode::ZeroPage_X),
+        OpCode::new(0x4D, Mnemonic::EOR, 3,4,AddressingMode::Absolute),
+        OpCode::new(0x5D, Mnemonic::EOR, 3,4,AddressingMode::Absolute_X),
+        OpCode::new(0x59, Mnemonic::EOR, 3,4,AddressingMode::Absolute_Y),
+        OpCode::new(0x41, Mnemonic::EOR, 2,6,AddressingMode::Indirect_X),
+        OpCode::new(0x51, Mnemonic::EOR, 2,5,AddressingMode::Indirect_Y),
+
+        OpCode::new(0x09, Mnemonic::ORA, 2,2,AddressingMode::Immediate),
+        OpCode::new(0x05, Mnemonic::ORA, 2,3,AddressingMode::ZeroPage),
+        OpCode::new(0x15, Mnemonic::ORA, 2,4,AddressingMode::ZeroPage_X),
+        OpCode::new(0x0D, Mnemonic::ORA, 3,4,AddressingMode::Absolute),
+        OpCode::new(0x1D, Mnemonic::ORA, 3,4,AddressingMode::Absolute_X),
+        OpCode::new(0x19, Mnemonic::ORA, 3,4,AddressingMode::Absolute_Y),
+        OpCode::new(0x01, Mnemonic::ORA, 2,6,AddressingMode::Indirect_X),
+        OpCode::new(0x11, Mnemonic::ORA, 2,5,AddressingMode::Indirect_Y),
+
+        // Shifts
+        OpCode::new(0x0A, Mnemonic::ASL, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0x06, Mnemonic::ASL, 2,5,AddressingMode::ZeroPage),
+        OpCode::new(0x16, Mnemonic::ASL, 2,6,AddressingMode::ZeroPage_X),
+        OpCode::new(0x0E, Mnemonic::ASL, 3,6,AddressingMode::Absolute),
+        OpCode::new(0x1E, Mnemonic::ASL, 3,7,AddressingMode::Absolute_X),
+
+        OpCode::new(0x4A, Mnemonic::LSR, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0x46, Mnemonic::LSR, 2,5,AddressingMode::ZeroPage),
+        OpCode::new(0x56, Mnemonic::LSR, 2,6,AddressingMode::ZeroPage_X),
+        OpCode::new(0x4E, Mnemonic::LSR, 3,6,AddressingMode::Absolute),
+        OpCode::new(0x5E, Mnemonic::LSR, 3,7,AddressingMode::Absolute_X),
+
+        OpCode::new(0x2A, Mnemonic::ROL, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0x26, Mnemonic::ROL, 2,5,AddressingMode::ZeroPage),
+        OpCode::new(0x36, Mnemonic::ROL, 2,6,AddressingMode::ZeroPage_X),
+        OpCode::new(0x2E, Mnemonic::ROL, 3,6,AddressingMode::Absolute),
+        OpCode::new(0x3E, Mnemonic::ROL, 3,7,AddressingMode::Absolute_X),
+
+        OpCode::new(0x6A, Mnemonic::ROR, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0x66, Mnemonic::ROR, 2,5,AddressingMode::ZeroPage),
+        OpCode::new(0x76, Mnemonic::ROR, 2,6,AddressingMode::ZeroPage_X),
+        OpCode::new(0x6E, Mnemonic::ROR, 3,6,AddressingMode::Absolute),
+        OpCode::new(0x7E, Mnemonic::ROR, 3,7,AddressingMode::Absolute_X),
+
+        // Increments and decrements
+        OpCode::new(0xE6, Mnemonic::INC, 2,5,AddressingMode::ZeroPage),
+        OpCode::new(0xF6, Mnemonic::INC, 2,6,AddressingMode::ZeroPage_X),
+        OpCode::new(0xEE, Mnemonic::INC, 3,6,AddressingMode::Absolute),
+        OpCode::new(0xFE, Mnemonic::INC, 3,7,AddressingMode::Absolute_X),
+
+        OpCode::new(0xE8, Mnemonic::INX, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0xC8, Mnemonic::INY, 1,2,AddressingMode::NoneAddressing),
+
+        OpCode::new(0xC6, Mnemonic::DEC, 2,5,AddressingMode::ZeroPage),
+        OpCode::new(0xD6, Mnemonic::DEC, 2,6,AddressingMode::ZeroPage_X),
+        OpCode::new(0xCE, Mnemonic::DEC, 3,6,AddressingMode::Absolute),
+        OpCode::new(0xDE, Mnemonic::DEC, 3,7,AddressingMode::Absolute_X),
+
+        OpCode::new(0xCA, Mnemonic::DEX, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0x88, Mnemonic::DEY, 1,2,AddressingMode::NoneAddressing),
+
+        // Comparisons
+        OpCode::new(0xC9, Mnemonic::CMP, 2,2,AddressingMode::Immediate),
+        OpCode::new(0xC5, Mnemonic::CMP, 2,3,AddressingMode::ZeroPage),
+        OpCode::new(0xD5, Mnemonic::CMP, 2,4,AddressingMode::ZeroPage_X),
+        OpCode::new(0xCD, Mnemonic::CMP, 3,4,AddressingMode::Absolute),
+        OpCode::new(0xDD, Mnemonic::CMP, 3,4,AddressingMode::Absolute_X),
+        OpCode::new(0xD9, Mnemonic::CMP, 3,4,AddressingMode::Absolute_Y),
+        OpCode::new(0xC1, Mnemonic::CMP, 2,6,AddressingMode::Indirect_X),
+        OpCode::new(0xD1, Mnemonic::CMP, 2,5,AddressingMode::Indirect_Y),
+
+        OpCode::new(0xE0, Mnemonic::CPX, 2,2,AddressingMode::Immediate),
+        OpCode::new(0xE4, Mnemonic::CPX, 2,3,AddressingMode::ZeroPage),
+        OpCode::new(0xEC, Mnemonic::CPX, 3,4,AddressingMode::Absolute),
+
+        OpCode::new(0xC0, Mnemonic::CPY, 2,2,AddressingMode::Immediate),
+        OpCode::new(0xC4, Mnemonic::CPY, 2,3,AddressingMode::ZeroPage),
+        OpCode::new(0xCC, Mnemonic::CPY, 3,4,AddressingMode::Absolute),
+
+        OpCode::new(0x24, Mnemonic::BIT, 2,3,AddressingMode::ZeroPage),
+        OpCode::new(0x2C, Mnemonic::BIT, 3,4,AddressingMode::Absolute),
+
+        // Branches
+        OpCode::new(0x10, Mnemonic::BPL, 2,2,AddressingMode::NoneAddressing),
+        OpCode::new(0x30, Mnemonic::BMI, 2,2,AddressingMode::NoneAddressing),
+        OpCode::new(0x50, Mnemonic::BVC, 2,2,AddressingMode::NoneAddressing),
+        OpCode::new(0x70, Mnemonic::BVS, 2,2,AddressingMode::NoneAddressing),
+        OpCode::new(0x90, Mnemonic::BCC, 2,2,AddressingMode::NoneAddressing),
+        OpCode::new(0xB0, Mnemonic::BCS, 2,2,AddressingMode::NoneAddressing),
+        OpCode::new(0xD0, Mnemonic::BNE, 2,2,AddressingMode::NoneAddressing),
+        OpCode::new(0xF0, Mnemonic::BEQ, 2,2,AddressingMode::NoneAddressing),
+
+        // Jumps and subroutines
+        OpCode::new(0x4C, Mnemonic::JMP, 3,3,AddressingMode::Absolute),
+        OpCode::new(0x6C, Mnemonic::JMP, 3,5,AddressingMode::NoneAddressing),
+        OpCode::new(0x20, Mnemonic::JSR, 3,6,AddressingMode::Absolute),
+        OpCode::new(0x60, Mnemonic::RTS, 1,6,AddressingMode::NoneAddressing),
+        OpCode::new(0x40, Mnemonic::RTI, 1,6,AddressingMode::NoneAddressing),
+
+        // Flags
+        OpCode::new(0x18, Mnemonic::CLC, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0xD8, Mnemonic::CLD, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0x58, Mnemonic::CLI, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0xB8, Mnemonic::CLV, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0x38, Mnemonic::SEC, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0xF8, Mnemonic::SED, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0x78, Mnemonic::SEI, 1,2,AddressingMode::NoneAddressing),
+
+        // Stack
+        OpCode::new(0x48, Mnemonic::PHA, 1,3,AddressingMode::NoneAddressing),
+        OpCode::new(0x08, Mnemonic::PHP, 1,3,AddressingMode::NoneAddressing),
+        OpCode::new(0x68, Mnemonic::PLA, 1,4,AddressingMode::NoneAddressing),
+        OpCode::new(0x28, Mnemonic::PLP, 1,4,AddressingMode::NoneAddressing),
+
+        // LDA
+        OpCode::new(0xA9, Mnemonic::LDA, 2,2,AddressingMode::Immediate),
+        OpCode::new(0xA5, Mnemonic::LDA, 2,3,AddressingMode::ZeroPage),
+        OpCode::new(0xB5, Mnemonic::LDA, 2,4,AddressingMode::ZeroPage_X),
+        OpCode::new(0xAD, Mnemonic::LDA, 3,4,AddressingMode::Absolute),
+        OpCode::new(0xBD, Mnemonic::LDA, 3,4,AddressingMode::Absolute_X),
+        OpCode::new(0xB9, Mnemonic::LDA, 3,4,AddressingMode::Absolute_Y),
+        OpCode::new(0xA1, Mnemonic::LDA, 2,6,AddressingMode::Indirect_X),
+        OpCode::new(0xB1, Mnemonic::LDA, 2,5,AddressingMode::Indirect_Y),
+
+        // LDX
+        OpCode::new(0xA2, Mnemonic::LDX, 2,2,AddressingMode::Immediate),
+        OpCode::new(0xA6, Mnemonic::LDX, 2,3,AddressingMode::ZeroPage),
+        OpCode::new(0xB6, Mnemonic::LDX, 2,4,AddressingMode::ZeroPage_Y),
+        OpCode::new(0xAE, Mnemonic::LDX, 3,4,AddressingMode::Absolute),
+        OpCode::new(0xBE, Mnemonic::LDX, 3,4,AddressingMode::Absolute_Y),
+
+        // LDY
+        OpCode::new(0xA0, Mnemonic::LDY, 2,2,AddressingMode::Immediate),
+        OpCode::new(0xA4, Mnemonic::LDY, 2,3,AddressingMode::ZeroPage),
+        OpCode::new(0xB4, Mnemonic::LDY, 2,4,AddressingMode::ZeroPage_X),
+        OpCode::new(0xAC, Mnemonic::LDY, 3,4,AddressingMode::Absolute),
+        OpCode::new(0xBC, Mnemonic::LDY, 3,4,AddressingMode::Absolute_X),
+
+        // STA
+        OpCode::new(0x85, Mnemonic::STA, 2,3,AddressingMode::ZeroPage),
+        OpCode::new(0x95, Mnemonic::STA, 2,4,AddressingMode::ZeroPage_X),
+        OpCode::new(0x8D, Mnemonic::STA, 3,4,AddressingMode::Absolute),
+        OpCode::new(0x9D, Mnemonic::STA, 3,5,AddressingMode::Absolute_X),
+        OpCode::new(0x99, Mnemonic::STA, 3,5,AddressingMode::Absolute_Y),
+        OpCode::new(0x81, Mnemonic::STA, 2,6,AddressingMode::Indirect_X),
+        OpCode::new(0x91, Mnemonic::STA, 2,6,AddressingMode::Indirect_Y),
+
+        // STX
+        OpCode::new(0x86, Mnemonic::STX, 2,3,AddressingMode::ZeroPage),
+        OpCode::new(0x96, Mnemonic::STX, 2,4,AddressingMode::ZeroPage_Y),
+        OpCode::new(0x8E, Mnemonic::STX, 3,4,AddressingMode::Absolute),
+
+        // STY
+        OpCode::new(0x84, Mnemonic::STY, 2,3,AddressingMode::ZeroPage),
+        OpCode::new(0x94, Mnemonic::STY, 2,4,AddressingMode::ZeroPage_X),
+        OpCode::new(0x8C, Mnemonic::STY, 3,4,AddressingMode::Absolute),
+
+        // Transfers
+        OpCode::new(0xAA, Mnemonic::TAX, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0xA8, Mnemonic::TAY, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0xBA, Mnemonic::TSX, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0x8A, Mnemonic::TXA, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0x9A, Mnemonic::TXS, 1,2,AddressingMode::NoneAddressing),
+        OpCode::new(0x98, Mnemonic::TYA, 1,2,AddressingMode::NoneAddressing),
+
+        // Unofficial opcodes
+        OpCode::unofficial(0xA7, Mnemonic::LAX, 2,3,AddressingMode::ZeroPage),
+        OpCode::unofficial(0xB7, Mnemonic::LAX, 2,4,AddressingMode::ZeroPage_Y),
+        OpCode::unofficial(0xAF, Mnemonic::LAX, 3,4,AddressingMode::Absolute),
+        OpCode::unofficial(0xBF, Mnemonic::LAX, 3,4,AddressingMode::Absolute_Y),
+        OpCode::unofficial(0xA3, Mnemonic::LAX, 2,6,AddressingMode::Indirect_X),
+        OpCode::unofficial(0xB3, Mnemonic::LAX, 2,5,AddressingMode::Indirect_Y),
+
+        OpCode::unofficial(0x87, Mnemonic::SAX, 2,3,AddressingMode::ZeroPage),
+        OpCode::unofficial(0x97, Mnemonic::SAX, 2,4,AddressingMode::ZeroPage_Y),
+        OpCode::unofficial(0x8F, Mnemonic::SAX, 3,4,AddressingMode::Absolute),
+        OpCode::unofficial(0x83, Mnemonic::SAX, 2,6,AddressingMode::Indirect_X),
+
+        OpCode::unofficial(0x07, Mnemonic::SLO, 2,5,AddressingMode::ZeroPage),
+        OpCode::unofficial(0x17, Mnemonic::SLO, 2,6,AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0x0F, Mnemonic::SLO, 3,6,AddressingMode::Absolute),
+        OpCode::unofficial(0x1F, Mnemonic::SLO, 3,7,AddressingMode::Absolute_X),
+        OpCode::unofficial(0x1B, Mnemonic::SLO, 3,7,AddressingMode::Absolute_Y),
+        OpCode::unofficial(0x03, Mnemonic::SLO, 2,8,AddressingMode::Indirect_X),
+        OpCode::unofficial(0x13, Mnemonic::SLO, 2,8,AddressingMode::Indirect_Y),
+
+        OpCode::unofficial(0x27, Mnemonic::RLA, 2,5,AddressingMode::ZeroPage),
+        OpCode::unofficial(0x37, Mnemonic::RLA, 2,6,AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0x2F, Mnemonic::RLA, 3,6,AddressingMode::Absolute),
+        OpCode::unofficial(0x3F, Mnemonic::RLA, 3,7,AddressingMode::Absolute_X),
+        OpCode::unofficial(0x3B, Mnemonic::RLA, 3,7,AddressingMode::Absolute_Y),
+        OpCode::unofficial(0x23, Mnemonic::RLA, 2,8,AddressingMode::Indirect_X),
+        OpCode::unofficial(0x33, Mnemonic::RLA, 2,8,AddressingMode::Indirect_Y),
+
+        OpCode::unofficial(0x47, Mnemonic::SRE, 2,5,AddressingMode::ZeroPage),
+        OpCode::unofficial(0x57, Mnemonic::SRE, 2,6,AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0x4F, Mnemonic::SRE, 3,6,AddressingMode::Absolute),
+        OpCode::unofficial(0x5F, Mnemonic::SRE, 3,7,AddressingMode::Absolute_X),
+        OpCode::unofficial(0x5B, Mnemonic::SRE, 3,7,AddressingMode::Absolute_Y),
+        OpCode::unofficial(0x43, Mnemonic::SRE, 2,8,AddressingMode::Indirect_X),
+        OpCode::unofficial(0x53, Mnemonic::SRE, 2,8,AddressingMode::Indirect_Y),
+
+        OpCode::unofficial(0x67, Mnemonic::RRA, 2,5,AddressingMode::ZeroPage),
+        OpCode::unofficial(0x77, Mnemonic::RRA, 2,6,AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0x6F, Mnemonic::RRA, 3,6,AddressingMode::Absolute),
+        OpCode::unofficial(0x7F, Mnemonic::RRA, 3,7,AddressingMode::Absolute_X),
+        OpCode::unofficial(0x7B, Mnemonic::RRA, 3,7,AddressingMode::Absolute_Y),
+        OpCode::unofficial(0x63, Mnemonic::RRA, 2,8,AddressingMode::Indirect_X),
+        OpCode::unofficial(0x73, Mnemonic::RRA, 2,8,AddressingMode::Indirect_Y),
+
+        OpCode::unofficial(0xC7, Mnemonic::DCP, 2,5,AddressingMode::ZeroPage),
+        OpCode::unofficial(0xD7, Mnemonic::DCP, 2,6,AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0xCF, Mnemonic::DCP, 3,6,AddressingMode::Absolute),
+        OpCode::unofficial(0xDF, Mnemonic::DCP, 3,7,AddressingMode::Absolute_X),
+        OpCode::unofficial(0xDB, Mnemonic::DCP, 3,7,AddressingMode::Absolute_Y),
+        OpCode::unofficial(0xC3, Mnemonic::DCP, 2,8,AddressingMode::Indirect_X),
+        OpCode::unofficial(0xD3, Mnemonic::DCP, 2,8,AddressingMode::Indirect_Y),
+
+        OpCode::unofficial(0xE7, Mnemonic::ISB, 2,5,AddressingMode::ZeroPage),
+        OpCode::unofficial(0xF7, Mnemonic::ISB, 2,6,AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0xEF, Mnemonic::ISB, 3,6,AddressingMode::Absolute),
+        OpCode::unofficial(0xFF, Mnemonic::ISB, 3,7,AddressingMode::Absolute_X),
+        OpCode::unofficial(0xFB, Mnemonic::ISB, 3,7,AddressingMode::Absolute_Y),
+        OpCode::unofficial(0xE3, Mnemonic::ISB, 2,8,AddressingMode::Indirect_X),
+        OpCode::unofficial(0xF3, Mnemonic::ISB, 2,8,AddressingMode::Indirect_Y),
+
+        OpCode::unofficial(0x0B, Mnemonic::ANC, 2,2,AddressingMode::Immediate),
+        OpCode::unofficial(0x2B, Mnemonic::ANC, 2,2,AddressingMode::Immediate),
+        OpCode::unofficial(0x4B, Mnemonic::ALR, 2,2,AddressingMode::Immediate),
+        OpCode::unofficial(0x6B, Mnemonic::ARR, 2,2,AddressingMode::Immediate),
+        OpCode::unofficial(0xCB, Mnemonic::AXS, 2,2,AddressingMode::Immediate),
+        OpCode::unofficial(0xEB, Mnemonic::SBC, 2,2,AddressingMode::Immediate),
+
+        OpCode::unofficial(0x1A, Mnemonic::NOP, 1,2,AddressingMode::NoneAddressing),
+        OpCode::unofficial(0x3A, Mnemonic::NOP, 1,2,AddressingMode::NoneAddressing),
+        OpCode::unofficial(0x5A, Mnemonic::NOP, 1,2,AddressingMode::NoneAddressing),
+        OpCode::unofficial(0x7A, Mnemonic::NOP, 1,2,AddressingMode::NoneAddressing),
+        OpCode::unofficial(0xDA, Mnemonic::NOP, 1,2,AddressingMode::NoneAddressing),
+        OpCode::unofficial(0xFA, Mnemonic::NOP, 1,2,AddressingMode::NoneAddressing),
+        OpCode::unofficial(0x80, Mnemonic::NOP, 2,2,AddressingMode::Immediate),
+        OpCode::unofficial(0x82, Mnemonic::NOP, 2,2,AddressingMode::Immediate),
+        OpCode::unofficial(0x89, Mnemonic::NOP, 2,2,AddressingMode::Immediate),
+        OpCode::unofficial(0xC2, Mnemonic::NOP, 2,2,AddressingMode::Immediate),
+        OpCode::unofficial(0xE2, Mnemonic::NOP, 2,2,AddressingMode::Immediate),
+        OpCode::unofficial(0x04, Mnemonic::NOP, 2,3,AddressingMode::ZeroPage),
+        OpCode::unofficial(0x44, Mnemonic::NOP, 2,3,AddressingMode::ZeroPage),
+        OpCode::unofficial(0x64, Mnemonic::NOP, 2,3,AddressingMode::ZeroPage),
+        OpCode::unofficial(0x14, Mnemonic::NOP, 2,4,AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0x34, Mnemonic::NOP, 2,4,AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0x54, Mnemonic::NOP, 2,4,AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0x74, Mnemonic::NOP, 2,4,AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0xD4, Mnemonic::NOP, 2,4,AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0xF4, Mnemonic::NOP, 2,4,AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0x0C, Mnemonic::NOP, 3,4,AddressingMode::Absolute),
+        OpCode::unofficial(0x1C, Mnemonic::NOP, 3,4,AddressingMode::Absolute_X),
+        OpCode::unofficial(0x3C, Mnemonic::NOP, 3,4,AddressingMode::Absolute_X),
+        OpCode::unofficial(0x5C, Mnemonic::NOP, 3,4,AddressingMode::Absolute_X),
+        OpCode::unofficial(0x7C, Mnemonic::NOP, 3,4,AddressingMode::Absolute_X),
+        OpCode::unofficial(0xDC, Mnemonic::NOP, 3,4,AddressingMode::Absolute_X),
+        OpCode::unofficial(0xFC, Mnemonic::NOP, 3,4,AddressingMode::Absolute_X),
+    ];
+
+    fn page_cross(addr1: u16, addr2: u16) -> bool {
+        addr1 & 0xFF00 != addr2 & 0xFF00
+    }
+
+    /// `OPCODES` indexed by opcode byte, so decoding is a single lookup.
+    pub static OPCODE_TABLE: [Option<&'static OpCode>; 256] = {
+        let mut table = [None; 256];
+        let mut i = 0;
+        while i < OPCODES.len() {
+            table[OPCODES[i].opcode as usize] = Some(&OPCODES[i]);
+            i += 1;
         }
+        table
+    };
+
+    pub fn find_opcode_by_instruction(instruction: u8) -> Option<&'static OpCode> {
+        OPCODE_TABLE[instruction as usize]
+    }
 
-        None
+    impl Default for CPU {
+        fn default() -> Self {
+            Self::new()
+        }
     }
 
     impl CPU {
+        /// A CPU attached to 64KB of flat RAM.
         pub fn new() -> Self {
+            CPU::with_bus(FlatMemory::new())
+        }
+    }
+
+    impl<B: Bus> CPU<B> {
+        pub fn with_bus(bus: B) -> Self {
             CPU {
                 register_a: 0,
-                status: 0,
+                status: CpuFlags::power_on(),
                 program_counter: 0,
 
                 register_x: 0,
                 register_y: 0,
+                stack_pointer: STACK_RESET,
+                cycles: 0,
+                unknown_opcode_policy: UnknownOpcodePolicy::Halt,
+                brk_behavior: BrkBehavior::Halt,
+                jammed: false,
+                nmi_input: false,
+                nmi_line: false,
+                nmi_pending: false,
+                irq_line: false,
 
-                memory: [0; 0xFFFF]
-
+                bus,
             }
         }
 
-        
-            
-        pub fn mem_read(&self, addr: u16) -> u8 {
-            self.memory[addr as usize]
+        pub fn mem_read(&mut self, addr: u16) -> u8 {
+            self.bus.read(addr)
         }
 
         fn mem_read_u16(&mut self, pos: u16) -> u16 {
             let lo = self.mem_read(pos) as u16;
-            let hi = self.mem_read(pos + 1) as u16;
-            (hi << 8) | (lo as u16)
+            let hi = self.mem_read(pos.wrapping_add(1)) as u16;
+            (hi << 8) | lo
         }
-    
+
         pub fn mem_write(&mut self, addr: u16, data: u8) {
-            self.memory[addr as usize] = data;
+            self.bus.write(addr, data);
+        }
+
+        /// Read-modify-write instructions write the unmodified value back
+        /// on the cycle before the result, which registers with side
+        /// effects can see.
+        fn write_modified(&mut self, addr: u16, data: u8, result: u8) {
+            self.mem_write(addr, data);
+            self.mem_write(addr, result);
         }
 
         fn mem_write_u16(&mut self, pos: u16, data: u16) {
             let hi = (data >> 8) as u8;
             let lo = (data & 0xff) as u8;
             self.mem_write(pos, lo);
-            self.mem_write(pos + 1, hi);
+            self.mem_write(pos.wrapping_add(1), hi);
         }
-     
-        
+
         pub fn reset(&mut self) {
             self.register_a = 0;
             self.register_x = 0;
-            self.status = 0;
-     
-            self.program_counter = self.mem_read_u16(0xFFFC);
+            self.register_y = 0;
+            self.stack_pointer = STACK_RESET;
+            self.status = CpuFlags::power_on();
+            self.jammed = false;
+            self.nmi_pending = false;
+            // The reset sequence itself takes 7 cycles before the first fetch.
+            self.cycles = 7;
+
+            self.program_counter = self.mem_read_u16(RESET_VECTOR);
         }
-     
+
         pub fn load(&mut self, program: Vec<u8>) {
-            self.memory[0x8000 .. (0x8000 + program.len())].copy_from_slice(&program[..]);
-            self.mem_write_u16(0xFFFC, 0x8000);
+            for (i, byte) in program.iter().enumerate() {
+                self.mem_write(0x8000 + i as u16, *byte);
+            }
+            self.mem_write_u16(RESET_VECTOR, 0x8000);
         }
-     
-        pub fn load_and_run(&mut self, program: Vec<u8>) {
+
+        pub fn load_and_run(&mut self, program: Vec<u8>) -> Result<(), CpuError> {
             self.load(program);
             self.reset();
             self.run()
         }
-     
-        fn get_operand_address(&mut self, mode: &AddressingMode) -> u16 {
 
-            match mode {
-                AddressingMode::Immediate => self.program_counter,
-     
-                AddressingMode::ZeroPage  => self.mem_read(self.program_counter) as u16,
-               
-                AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
-             
+        /// Resolves the effective address for `mode`. The flag is true when an
+        /// indexed mode crossed a page boundary, which costs reads a cycle.
+        fn get_operand_address(&mut self, mode: &AddressingMode) -> Result<(u16, bool), CpuError> {
+            let address = match mode {
+                AddressingMode::Immediate => (self.program_counter, false),
+
+                AddressingMode::ZeroPage => (self.mem_read(self.program_counter) as u16, false),
+
+                AddressingMode::Absolute => (self.mem_read_u16(self.program_counter), false),
+
                 AddressingMode::ZeroPage_X => {
                     let pos = self.mem_read(self.program_counter);
-                    let addr = pos.wrapping_add(self.register_x) as u16;
-                    addr
+                    (pos.wrapping_add(self.register_x) as u16, false)
                 }
                 AddressingMode::ZeroPage_Y => {
                     let pos = self.mem_read(self.program_counter);
-                    let addr = pos.wrapping_add(self.register_y) as u16;
-                    addr
+                    (pos.wrapping_add(self.register_y) as u16, false)
                 }
-     
+
                 AddressingMode::Absolute_X => {
                     let base = self.mem_read_u16(self.program_counter);
                     let addr = base.wrapping_add(self.register_x as u16);
-                    addr
+                    (addr, page_cross(base, addr))
                 }
                 AddressingMode::Absolute_Y => {
                     let base = self.mem_read_u16(self.program_counter);
                     let addr = base.wrapping_add(self.register_y as u16);
-                    addr
+                    (addr, page_cross(base, addr))
                 }
-     
+
                 AddressingMode::Indirect_X => {
                     let base = self.mem_read(self.program_counter);
-     
-                    let ptr: u8 = (base as u8).wrapping_add(self.register_x);
+
+                    let ptr: u8 = base.wrapping_add(self.register_x);
                     let lo = self.mem_read(ptr as u16);
                     let hi = self.mem_read(ptr.wrapping_add(1) as u16);
-                    (hi as u16) << 8 | (lo as u16)
+                    ((hi as u16) << 8 | (lo as u16), false)
                 }
                 AddressingMode::Indirect_Y => {
                     let base = self.mem_read(self.program_counter);
-     
+
                     let lo = self.mem_read(base as u16);
-                    let hi = self.mem_read((base as u8).wrapping_add(1) as u16);
+                    let hi = self.mem_read(base.wrapping_add(1) as u16);
                     let deref_base = (hi as u16) << 8 | (lo as u16);
                     let deref = deref_base.wrapping_add(self.register_y as u16);
-                    deref
+                    (deref, page_cross(deref_base, deref))
                 }
-              
+
                 AddressingMode::NoneAddressing => {
-                    panic!("mode {:?} is not supported", mode);
+                    return Err(CpuError::InvalidAddressingMode {
+                        pc: self.program_counter.wrapping_sub(1),
+                        mode: *mode,
+                    });
                 }
+            };
+
+            Ok(address)
+        }
+
+        fn read_operand(&mut self, (addr, page_cross): (u16, bool)) -> u8 {
+            if page_cross {
+                self.cycles += 1;
             }
-     
+            self.mem_read(addr)
+        }
+
+        /// Pushes a byte onto the hardware stack in page $01. The stack grows
+        /// downwards and the pointer wraps within the page like the real chip.
+        pub fn stack_push(&mut self, data: u8) {
+            self.mem_write(STACK + self.stack_pointer as u16, data);
+            self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+        }
+
+        pub fn stack_pop(&mut self) -> u8 {
+            self.stack_pointer = self.stack_pointer.wrapping_add(1);
+            self.mem_read(STACK + self.stack_pointer as u16)
+        }
+
+        pub fn stack_push_u16(&mut self, data: u16) {
+            let hi = (data >> 8) as u8;
+            let lo = (data & 0xff) as u8;
+            self.stack_push(hi);
+            self.stack_push(lo);
         }
 
+        pub fn stack_pop_u16(&mut self) -> u16 {
+            let lo = self.stack_pop() as u16;
+            let hi = self.stack_pop() as u16;
+            hi << 8 | lo
+        }
 
-        fn lda(&mut self, mode: &AddressingMode) {
-            let addr = self.get_operand_address(mode);
-            let value = self.mem_read(addr);
-           
+        fn set_register_a(&mut self, value: u8) {
             self.register_a = value;
             self.update_zero_and_negative_flags(self.register_a);
         }
-      
-        fn sta(&mut self, mode: &AddressingMode) {
-            let addr = self.get_operand_address(mode);
-            self.mem_write(addr, self.register_a);
+
+        fn lda(&mut self, operand: (u16, bool)) {
+            let value = self.read_operand(operand);
+
+            self.set_register_a(value);
         }
 
-        fn tax(&mut self) {
-            self.register_x = self.register_a;
+        fn ldx(&mut self, operand: (u16, bool)) {
+            self.register_x = self.read_operand(operand);
             self.update_zero_and_negative_flags(self.register_x);
         }
-        fn tay(&mut self) {
-            self.register_y = self.register_a;
-            self.update_zero_and_negative_flags(self.register_x);
+
+        fn ldy(&mut self, operand: (u16, bool)) {
+            self.register_y = self.read_operand(operand);
+            self.update_zero_and_negative_flags(self.register_y);
+        }
+
+        fn sta(&mut self, operand: (u16, bool)) {
+            let (addr, _) = operand;
+            self.mem_write(addr, self.register_a);
         }
-        
 
+        fn stx(&mut self, operand: (u16, bool)) {
+            let (addr, _) = operand;
+            self.mem_write(addr, self.register_x);
+        }
 
-        fn inx(&mut self) {
-            self.register_x = self.register_x.wrapping_add(1);
-            self.update_zero_and_negative_flags(self.register_x);
+        fn sty(&mut self, operand: (u16, bool)) {
+            let (addr, _) = operand;
+            self.mem_write(addr, self.register_y);
         }
 
-        fn update_zero_and_negative_flags(&mut self, result: u8) {
-             if result == 0 {
-                 self.status = self.status | 0b0000_0010;
-             } else {
-                 self.status = self.status & 0b1111_1101;
-             }
-     
-             if result & 0b1000_0000 != 0 {
-                 self.status = self.status | 0b1000_0000;
-             } else {
-                 self.status = self.status & 0b0111_1111;
-             }
-         }
-
-        pub fn run(&mut self) {
+        /// Adds `data` and the carry flag to the accumulator. The 2A03 has no
+        /// decimal mode, so the D flag is ignored.
+        fn add_to_register_a(&mut self, data: u8) {
+            let sum = self.register_a as u16
+                + data as u16
+                + self.status.contains(CpuFlags::CARRY) as u16;
 
-            loop {
-                let instruction = self.mem_read(self.program_counter);
-                let op_code = find_opcode_by_instruction(instruction).unwrap();
-                self.program_counter += 1;
-                
-
-                match op_code.name  {
-                    // LDA
-                    "LDA" => {
-                        self.lda(&op_code.adressing_mode);
-                        self.program_counter += op_code.takes_bytes - 1;
-                    }
-                    // STA
-                    "STA" => {
-                        self.sta(&op_code.adressing_mode);
-                        self.program_counter += op_code.takes_bytes - 1;
-                    }
-                    "TAX" => {
-                        self.tax()
-                    }
-                    "TAY" => {
-                        self.tay()
-                    }
-                    "INX" => {
-                        self.inx()
-                    }
-                    "BRK" => {
-                        return;
-                    }
-                    _ => todo!()
-                }
-            }
+            let result = sum as u8;
+
+            self.status.set(CpuFlags::CARRY, sum > 0xff);
+            self.status.set(CpuFlags::OVERFLOW, (data ^ result) & (result ^ self.register_a) & 0x80 != 0);
+
+            self.set_register_a(result);
         }
-    }
 
-}
+        fn adc(&mut self, operand: (u16, bool)) {
+            let value = self.read_operand(operand);
+            self.add_to_register_a(value);
+        }
 
+        fn sbc(&mut self, operand: (u16, bool)) {
+            let value = self.read_operand(operand);
+            self.add_to_register_a(!value);
+        }
 
+        fn and(&mut self, operand: (u16, bool)) {
+            let value = self.read_operand(operand);
+            self.set_register_a(self.register_a & value);
+        }
 
-#[cfg(test)]
-mod test {
-   use crate::cpu::{cpu::*, cpu_constants::*};
- 
-    fn endify(lo: u8, hi: u8) -> u16 {
-        ((hi << 8 ) | lo) as u16
-    }
+        fn eor(&mut self, operand: (u16, bool)) {
+            let value = self.read_operand(operand);
+            self.set_register_a(self.register_a ^ value);
+        }
 
-    fn dendify(bit: u16) -> (u8, u8) {
-        let hi = (bit >> 8) as u8;
-        let lo = (bit & 0xff) as u8;
-        (lo, hi)
-    }
+        fn ora(&mut self, operand: (u16, bool)) {
+            let value = self.read_operand(operand);
+            self.set_register_a(self.register_a | value);
+        }
 
-   #[test]
-   fn test_0xa9_lda_immediate_load_data() {
-       let mut cpu = CPU::new();
-       cpu.load_and_run(vec![LDA_IMMEDIATE, 0x05, BRK]);
-       assert_eq!(cpu.register_a, 0x05);
-       assert!(cpu.status & 0b0000_0010 == 0b00);
-       assert!(cpu.status & 0b1000_0000 == 0);
-   }
+        fn asl_accumulator(&mut self) {
+            let data = self.register_a;
+            self.status.set(CpuFlags::CARRY, data >> 7 == 1);
+            self.set_register_a(data << 1);
+        }
 
-    #[test]
-    fn test_0xa9_lda_zero_flag() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x00, BRK]);
-        assert!(cpu.status & 0b0000_0010 == 0b10);
-    }
+        fn asl(&mut self, operand: (u16, bool)) -> u8 {
+            let (addr, _) = operand;
+            let data = self.mem_read(addr);
+            self.status.set(CpuFlags::CARRY, data >> 7 == 1);
+            let result = data << 1;
+            self.write_modified(addr, data, result);
+            self.update_zero_and_negative_flags(result);
+            result
+        }
 
-    #[test]
-    fn test_lda_zero_page_from_memory() {
-        let mut cpu = CPU::new();
-        cpu.mem_write(0x10, 0x55);
- 
-        cpu.load_and_run(vec![LDA_ZP, 0x10, BRK]);
- 
-        assert_eq!(cpu.register_a, 0x55);
-    }
+        fn lsr_accumulator(&mut self) {
+            let data = self.register_a;
+            self.status.set(CpuFlags::CARRY, data & 1 == 1);
+            self.set_register_a(data >> 1);
+        }
 
+        fn lsr(&mut self, operand: (u16, bool)) -> u8 {
+            let (addr, _) = operand;
+            let data = self.mem_read(addr);
+            self.status.set(CpuFlags::CARRY, data & 1 == 1);
+            let result = data >> 1;
+            self.write_modified(addr, data, result);
+            self.update_zero_and_negative_flags(result);
+            result
+        }
 
-    #[test]
-    fn test_lda_zero_page_x_from_memory() {
-        let mut cpu = CPU::new();
-        cpu.mem_write(0x10 + 0x0F, 0x55);
- 
-        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x0F, TAX, LDA_ZPX, 0x10, BRK]);
+        fn rol_accumulator(&mut self) {
+            let data = self.register_a;
+            let old_carry = self.status.contains(CpuFlags::CARRY) as u8;
+            self.status.set(CpuFlags::CARRY, data >> 7 == 1);
+            self.set_register_a(data << 1 | old_carry);
+        }
 
-        assert_eq!(cpu.register_a, 0x55);
-    }
+        fn rol(&mut self, operand: (u16, bool)) -> u8 {
+            let (addr, _) = operand;
+            let data = self.mem_read(addr);
+            let old_carry = self.status.contains(CpuFlags::CARRY) as u8;
+            self.status.set(CpuFlags::CARRY, data >> 7 == 1);
+            let result = data << 1 | old_carry;
+            self.write_modified(addr, data, result);
+            self.update_zero_and_negative_flags(result);
+            result
+        }
 
-    #[test]
-    fn test_lda_absolute() {
-        let mut cpu = CPU::new();
-        cpu.mem_write(0xAAAA, 0x55);
- 
-        let (lo, hi) = dendify(0xAAAA);
+        fn ror_accumulator(&mut self) {
+            let data = self.register_a;
+            let old_carry = self.status.contains(CpuFlags::CARRY) as u8;
+            self.status.set(CpuFlags::CARRY, data & 1 == 1);
+            self.set_register_a(data >> 1 | old_carry << 7);
+        }
 
-        cpu.load_and_run(vec![LDA_ABS, lo, hi, BRK]);
+        fn ror(&mut self, operand: (u16, bool)) -> u8 {
+            let (addr, _) = operand;
+            let data = self.mem_read(addr);
+            let old_carry = self.status.contains(CpuFlags::CARRY) as u8;
+            self.status.set(CpuFlags::CARRY, data & 1 == 1);
+            let result = data >> 1 | old_carry << 7;
+            self.write_modified(addr, data, result);
+            self.update_zero_and_negative_flags(result);
+            result
+        }
 
-        assert_eq!(cpu.register_a, 0x55);
-    }
+        fn inc(&mut self, operand: (u16, bool)) -> u8 {
+            let (addr, _) = operand;
+            let data = self.mem_read(addr);
+            let result = data.wrapping_add(1);
+            self.write_modified(addr, data, result);
+            self.update_zero_and_negative_flags(result);
+            result
+        }
 
-    #[test]
-    fn test_lda_absolute_x() {
-        let mut cpu = CPU::new();
-        cpu.mem_write(0xAAAA + 0x80, 0x55);
- 
-        let (lo, hi) = dendify(0xAAAA);
+        fn dec(&mut self, operand: (u16, bool)) -> u8 {
+            let (addr, _) = operand;
+            let data = self.mem_read(addr);
+            let result = data.wrapping_sub(1);
+            self.write_modified(addr, data, result);
+            self.update_zero_and_negative_flags(result);
+            result
+        }
 
-        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x80, TAX, LDA_ABSX, lo, hi, BRK]);
+        fn compare(&mut self, operand: (u16, bool), compare_with: u8) {
+            let data = self.read_operand(operand);
+            self.status.set(CpuFlags::CARRY, compare_with >= data);
+            self.update_zero_and_negative_flags(compare_with.wrapping_sub(data));
+        }
 
-        assert_eq!(cpu.register_a, 0x55);
-    }
+        fn bit(&mut self, operand: (u16, bool)) {
+            let (addr, _) = operand;
+            let data = self.mem_read(addr);
 
-    #[test]
-    fn test_lda_absolute_y() {
-        let mut cpu = CPU::new();
-        cpu.mem_write(0xAAAA + 0x80, 0x55);
- 
-        let (lo, hi) = dendify(0xAAAA);
+            self.status.set(CpuFlags::ZERO, self.register_a & data == 0);
+            self.status.set(CpuFlags::NEGATIVE, data & 0b1000_0000 != 0);
+            self.status.set(CpuFlags::OVERFLOW, data & 0b0100_0000 != 0);
+        }
 
-        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x80, TAY, LDA_ABSY, lo, hi, BRK]);
+        fn lax(&mut self, operand: (u16, bool)) {
+            let value = self.read_operand(operand);
+            self.set_register_a(value);
+            self.register_x = value;
+        }
 
-        assert_eq!(cpu.register_a, 0x55);
-    }
+        fn sax(&mut self, operand: (u16, bool)) {
+            let (addr, _) = operand;
+            self.mem_write(addr, self.register_a & self.register_x);
+        }
 
-    #[test]
-   fn test_0xaa_tax_move_a_to_x() {
-       let mut cpu = CPU::new();
-       cpu.load_and_run(vec![LDA_IMMEDIATE, 0x0A, TAX, BRK]);
- 
-       assert_eq!(cpu.register_x, 0x0A)
-   }
+        fn dcp(&mut self, operand: (u16, bool)) {
+            let data = self.dec(operand);
+            self.status.set(CpuFlags::CARRY, self.register_a >= data);
+            self.update_zero_and_negative_flags(self.register_a.wrapping_sub(data));
+        }
 
-   #[test]
-   fn test_5_ops_working_together() {
-       let mut cpu = CPU::new();
-       cpu.load_and_run(vec![LDA_IMMEDIATE, 0xc0, TAX, INX, BRK]);
- 
-       assert_eq!(cpu.register_x, 0xc1)
-   }
+        fn isb(&mut self, operand: (u16, bool)) {
+            let data = self.inc(operand);
+            self.add_to_register_a(!data);
+        }
 
-    #[test]
-    fn test_inx_overflow() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![LDA_IMMEDIATE, 0xFF, TAX, INX, INX, BRK]);
-        assert_eq!(cpu.register_x, 1)
-    }
+        fn slo(&mut self, operand: (u16, bool)) {
+            let data = self.asl(operand);
+            self.set_register_a(self.register_a | data);
+        }
 
-    #[test]
-    fn test_sta() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![LDA_IMMEDIATE, 0xFF, STA_ZP, 0x16, BRK]);
-        assert_eq!(cpu.mem_read(0x16), 0xFF);
-    }
-}
\ No newline at end of file
+        fn rla(&mut self, operand: (u16, bool)) {
+            let data = self.rol(operand);
+            self.set_register_a(self.register_a & data);
+        }
+
+        fn sre(&mut self, operand: (u16, bool)) {
+            let data = self.lsr(operand);
+            self.set_register_a(self.register_a ^ data);
+        }
+
+        fn rra(&mut self, operand: (u16, bool)) {
+            let data = self.ror(operand);
+            self.add_to_register_a(data);
+        }
+
+        fn anc(&mut self, operand: (u16, bool)) {
+            self.and(operand);
+            self.status.set(CpuFlags::CARRY, self.status.contains(CpuFlags::NEGATIVE));
+        }
+
+        fn alr(&mut self, operand: (u16, bool)) {
+            self.and(operand);
+            self.lsr_accumulator();
+        }
+
+        /// AND followed by ROR A, except C and V come from bits 6 and 5 of
+        /// the result.
+        fn arr(&mut self, operand: (u16, bool)) {
+            self.and(operand);
+            self.ror_accumulator();
+            let result = self.register_a;
+            let bit_6 = (result >> 6) & 1;
+            let bit_5 = (result >> 5) & 1;
+            self.status.set(CpuFlags::CARRY, bit_6 == 1);
+            self.status.set(CpuFlags::OVERFLOW, bit_6 ^ bit_5 == 1);
+        }
+
+        /// X = (A & X) - operand, setting flags like CMP and ignoring carry in.
+        fn axs(&mut self, operand: (u16, bool)) {
+            let data = self.read_operand(operand);
+            let and = self.register_a & self.register_x;
+            self.status.set(CpuFlags::CARRY, and >= data);
+            self.register_x = and.wrapping_sub(data);
+            self.update_zero_and_negative_flags(self.register_x);
+        }
+
+        /// Taken branches cost one extra cycle, and one more when the target
+        /// is on a different page than the next instruction.
+        fn branch(&mut self, condition: bool) -> Flow {
+            if !condition {
+                return Flow::Next;
+            }
+
+            let jump = self.mem_read(self.program_counter) as i8;
+            let next = self.program_counter.wrapping_add(1);
+            let target = next.wrapping_add(jump as u16);
+
+            self.cycles += 1;
+            if page_cross(next, target) {
+                self.cycles += 1;
+            }
+
+            self.program_counter = target;
+            Flow::Jump
+        }
+
+        fn jmp_indirect(&mut self) {
+            let ptr = self.mem_read_u16(self.program_counter);
+
+            // The 6502 never carries into the high byte when fetching the
+            // target, so JMP ($10FF) reads $10FF and $1000.
+            self.program_counter = if ptr & 0x00FF == 0x00FF {
+                let lo = self.mem_read(ptr) as u16;
+                let hi = self.mem_read(ptr & 0xFF00) as u16;
+                hi << 8 | lo
+            } else {
+                self.mem_read_u16(ptr)
+            };
+        }
+
+        fn jsr(&mut self, target: u16) {
+            // The return address pushed is the last byte of the JSR itself.
+            self.stack_push_u16(self.program_counter.wrapping_add(1));
+            self.program_counter = target;
+        }
+
+        fn rts(&mut self) {
+            self.program_counter = self.stack_pop_u16().wrapping_add(1);
+        }
+
+        fn rti(&mut self) {
+            self.status = CpuFlags::from_pulled_byte(self.stack_pop());
+            self.program_counter = self.stack_pop_u16();
+        }
+
+        fn brk(&mut self) -> Flow {
+            match self.brk_behavior {
+                BrkBehavior::Halt => Flow::Halt,
+                BrkBehavior::Interrupt => {
+                    // BRK skips a padding byte, so the handler returns to PC + 2.
+                    self.program_counter = self.program_counter.wrapping_add(1);
+                    self.interrupt(IRQ_BRK_VECTOR, true);
+                    Flow::Jump
+                }
+            }
+        }
+
+        fn php(&mut self) {
+            self.stack_push(self.status.to_pushed_byte(true));
+        }
+
+        fn plp(&mut self) {
+            self.status = CpuFlags::from_pulled_byte(self.stack_pop());
+        }
+
+        fn pla(&mut self) {
+            let data = self.stack_pop();
+            self.set_register_a(data);
+        }
+
+        fn tax(&mut self) {
+            self.register_x = self.register_a;
+            self.update_zero_and_negative_flags(self.register_x);
+        }
+        fn tay(&mut self) {
+            self.register_y = self.register_a;
+            self.update_zero_and_negative_flags(self.register_y);
+        }
+        fn tsx(&mut self) {
+            self.register_x = self.stack_pointer;
+            self.update_zero_and_negative_flags(self.register_x);
+        }
+        fn txa(&mut self) {
+            self.set_register_a(self.register_x);
+        }
+        fn tya(&mut self) {
+            self.set_register_a(self.register_y);
+        }
+
+        fn inx(&mut self) {
+            self.register_x = self.register_x.wrapping_add(1);
+            self.update_zero_and_negative_flags(self.register_x);
+        }
+
+        fn iny(&mut self) {
+            self.register_y = self.register_y.wrapping_add(1);
+            self.update_zero_and_negative_flags(self.register_y);
+        }
+
+        fn dex(&mut self) {
+            self.register_x = self.register_x.wrapping_sub(1);
+            self.update_zero_and_negative_flags(self.register_x);
+        }
+
+        fn dey(&mut self) {
+            self.register_y = self.register_y.wrapping_sub(1);
+            self.update_zero_and_negative_flags(self.register_y);
+        }
+
+        fn update_zero_and_negative_flags(&mut self, result: u8) {
+            self.status.set(CpuFlags::ZERO, result == 0);
+            self.status.set(CpuFlags::NEGATIVE, result & 0b1000_0000 != 0);
+        }
+
+        /// Executes exactly one instruction and reports what it did.
+        /// A pending NMI or unmasked IRQ is serviced first, and the instruction
+        /// executed is then the first one of the handler.
+        pub fn step(&mut self) -> Result<StepResult, CpuError> {
+            self.step_with(|opcode| Self::DECODE[opcode as usize])
+        }
+
+        /// `step` decoding the way it did before `DECODE`: a linear search of
+        /// `OPCODES` and a match on the mnemonic for every instruction. Only
+        /// kept as the baseline for `benches/cpu.rs`.
+        #[doc(hidden)]
+        pub fn step_linear_scan(&mut self) -> Result<StepResult, CpuError> {
+            self.step_with(|opcode| {
+                let op_code = OPCODES.iter().find(|op| op.opcode == opcode)?;
+                Some((op_code, Self::handler(op_code.mnemonic, op_code.adressing_mode)))
+            })
+        }
+
+        fn step_with<F>(&mut self, decode: F) -> Result<StepResult, CpuError>
+        where
+            F: FnOnce(u8) -> Option<Decoded<B>>,
+        {
+            if self.jammed {
+                let pc = self.program_counter;
+                return Err(CpuError::Jammed { pc, opcode: self.mem_read(pc), interrupt: None });
+            }
+
+            let cycles_before = self.cycles;
+            let interrupt = self.poll_interrupts();
+            let result = self.execute(decode, interrupt);
+
+            // An interrupt serviced before a failing instruction has already
+            // spent its cycles, so the bus catches up on errors too.
+            self.tick_bus(self.cycles - cycles_before);
+            result.map(|result| StepResult { cycles: self.cycles - cycles_before, ..result })
+        }
+
+        /// Decodes and runs the instruction at PC. `step` fills in the
+        /// cycles of the result once the bus has caught up.
+        fn execute<F>(&mut self, decode: F, interrupt: Option<Interrupt>) -> Result<StepResult, CpuError>
+        where
+            F: FnOnce(u8) -> Option<Decoded<B>>,
+        {
+            let pc = self.program_counter;
+            let instruction = self.mem_read(pc);
+            let (op_code, handler) = match decode(instruction) {
+                Some(decoded) => decoded,
+                None => return self.unknown_opcode(pc, instruction, interrupt),
+            };
+
+            self.program_counter = self.program_counter.wrapping_add(1);
+            let mode = &op_code.adressing_mode;
+            self.cycles += op_code.takes_cycles as usize;
+
+            let (flow, operand_address) = match handler {
+                Handler::Implied(run) => {
+                    run(self);
+                    (Flow::Next, None)
+                }
+                Handler::Operand(run) => {
+                    let operand = self.get_operand_address(mode)?;
+                    run(self, operand);
+                    (Flow::Next, Some(operand.0))
+                }
+                Handler::Control(run) => (run(self), None),
+                Handler::Jump(run) => {
+                    let (target, _) = self.get_operand_address(mode)?;
+                    run(self, target);
+                    (Flow::Jump, Some(target))
+                }
+            };
+
+            if flow != Flow::Jump {
+                self.program_counter = self.program_counter.wrapping_add(op_code.takes_bytes - 1);
+            }
+
+            Ok(StepResult {
+                opcode: op_code.opcode,
+                mnemonic: op_code.mnemonic,
+                mode: *mode,
+                operand_address,
+                cycles: 0,
+                halted: flow == Flow::Halt,
+                interrupt,
+            })
+        }
+
+        /// Every opcode byte with its metadata and handler, so `step` decodes
+        /// and dispatches with one lookup.
+        const DECODE: [Option<Decoded<B>>; 256] = {
+            let mut table = [None; 256];
+            let mut i = 0;
+            while i < OPCODES.len() {
+                let op_code = &OPCODES[i];
+                let handler = Self::handler(op_code.mnemonic, op_code.adressing_mode);
+                table[op_code.opcode as usize] = Some((op_code, handler));
+                i += 1;
+            }
+            table
+        };
+
+        /// The code that runs `mnemonic` in `mode`. Evaluated at compile time
+        /// for `DECODE`, where an addressing mode the instruction cannot take
+        /// fails the build.
+        const fn handler(mnemonic: Mnemonic, mode: AddressingMode) -> Handler<B> {
+            use Handler::{Control, Implied, Jump, Operand};
+
+            let implied = matches!(mode, AddressingMode::NoneAddressing);
+            match (mnemonic, implied) {
+                (Mnemonic::LDA, false) => Operand(Self::lda),
+                (Mnemonic::LDX, false) => Operand(Self::ldx),
+                (Mnemonic::LDY, false) => Operand(Self::ldy),
+                (Mnemonic::STA, false) => Operand(Self::sta),
+                (Mnemonic::STX, false) => Operand(Self::stx),
+                (Mnemonic::STY, false) => Operand(Self::sty),
+
+                (Mnemonic::ADC, false) => Operand(Self::adc),
+                (Mnemonic::SBC, false) => Operand(Self::sbc),
+                (Mnemonic::AND, false) => Operand(Self::and),
+                (Mnemonic::EOR, false) => Operand(Self::eor),
+                (Mnemonic::ORA, false) => Operand(Self::ora),
+
+                (Mnemonic::ASL, true) => Implied(Self::asl_accumulator),
+                (Mnemonic::ASL, false) => Operand(|cpu, operand| {
+                    cpu.asl(operand);
+                }),
+                (Mnemonic::LSR, true) => Implied(Self::lsr_accumulator),
+                (Mnemonic::LSR, false) => Operand(|cpu, operand| {
+                    cpu.lsr(operand);
+                }),
+                (Mnemonic::ROL, true) => Implied(Self::rol_accumulator),
+                (Mnemonic::ROL, false) => Operand(|cpu, operand| {
+                    cpu.rol(operand);
+                }),
+                (Mnemonic::ROR, true) => Implied(Self::ror_accumulator),
+                (Mnemonic::ROR, false) => Operand(|cpu, operand| {
+                    cpu.ror(operand);
+                }),
+
+                (Mnemonic::INC, false) => Operand(|cpu, operand| {
+                    cpu.inc(operand);
+                }),
+                (Mnemonic::DEC, false) => Operand(|cpu, operand| {
+                    cpu.dec(operand);
+                }),
+                (Mnemonic::INX, true) => Implied(Self::inx),
+                (Mnemonic::INY, true) => Implied(Self::iny),
+                (Mnemonic::DEX, true) => Implied(Self::dex),
+                (Mnemonic::DEY, true) => Implied(Self::dey),
+
+                (Mnemonic::CMP, false) => Operand(|cpu, operand| cpu.compare(operand, cpu.register_a)),
+                (Mnemonic::CPX, false) => Operand(|cpu, operand| cpu.compare(operand, cpu.register_x)),
+                (Mnemonic::CPY, false) => Operand(|cpu, operand| cpu.compare(operand, cpu.register_y)),
+                (Mnemonic::BIT, false) => Operand(Self::bit),
+
+                (Mnemonic::BPL, true) => Control(|cpu| cpu.branch(!cpu.status.contains(CpuFlags::NEGATIVE))),
+                (Mnemonic::BMI, true) => Control(|cpu| cpu.branch(cpu.status.contains(CpuFlags::NEGATIVE))),
+                (Mnemonic::BVC, true) => Control(|cpu| cpu.branch(!cpu.status.contains(CpuFlags::OVERFLOW))),
+                (Mnemonic::BVS, true) => Control(|cpu| cpu.branch(cpu.status.contains(CpuFlags::OVERFLOW))),
+                (Mnemonic::BCC, true) => Control(|cpu| cpu.branch(!cpu.status.contains(CpuFlags::CARRY))),
+                (Mnemonic::BCS, true) => Control(|cpu| cpu.branch(cpu.status.contains(CpuFlags::CARRY))),
+                (Mnemonic::BNE, true) => Control(|cpu| cpu.branch(!cpu.status.contains(CpuFlags::ZERO))),
+                (Mnemonic::BEQ, true) => Control(|cpu| cpu.branch(cpu.status.contains(CpuFlags::ZERO))),
+
+                (Mnemonic::JMP, false) => Jump(|cpu, target| cpu.program_counter = target),
+                (Mnemonic::JMP, true) => Control(|cpu| {
+                    cpu.jmp_indirect();
+                    Flow::Jump
+                }),
+                (Mnemonic::JSR, false) => Jump(Self::jsr),
+                (Mnemonic::RTS, true) => Control(|cpu| {
+                    cpu.rts();
+                    Flow::Jump
+                }),
+                (Mnemonic::RTI, true) => Control(|cpu| {
+                    cpu.rti();
+                    Flow::Jump
+                }),
+                (Mnemonic::BRK, true) => Control(Self::brk),
+
+                (Mnemonic::CLC, true) => Implied(|cpu| cpu.status.remove(CpuFlags::CARRY)),
+                (Mnemonic::SEC, true) => Implied(|cpu| cpu.status.insert(CpuFlags::CARRY)),
+                (Mnemonic::CLV, true) => Implied(|cpu| cpu.status.remove(CpuFlags::OVERFLOW)),
+                (Mnemonic::CLD, true) => Implied(|cpu| cpu.status.remove(CpuFlags::DECIMAL)),
+                (Mnemonic::SED, true) => Implied(|cpu| cpu.status.insert(CpuFlags::DECIMAL)),
+                (Mnemonic::CLI, true) => Implied(|cpu| cpu.status.remove(CpuFlags::INTERRUPT_DISABLE)),
+                (Mnemonic::SEI, true) => Implied(|cpu| cpu.status.insert(CpuFlags::INTERRUPT_DISABLE)),
+
+                (Mnemonic::PHA, true) => Implied(|cpu| cpu.stack_push(cpu.register_a)),
+                (Mnemonic::PLA, true) => Implied(Self::pla),
+                (Mnemonic::PHP, true) => Implied(Self::php),
+                (Mnemonic::PLP, true) => Implied(Self::plp),
+
+                (Mnemonic::TAX, true) => Implied(Self::tax),
+                (Mnemonic::TAY, true) => Implied(Self::tay),
+                (Mnemonic::TSX, true) => Implied(Self::tsx),
+                (Mnemonic::TXA, true) => Implied(Self::txa),
+                (Mnemonic::TXS, true) => Implied(|cpu| cpu.stack_pointer = cpu.register_x),
+                (Mnemonic::TYA, true) => Implied(Self::tya),
+
+                (Mnemonic::NOP, true) => Implied(|_| {}),
+                // Unofficial NOPs with an operand still perform the read.
+                (Mnemonic::NOP, false) => Operand(|cpu, operand| {
+                    cpu.read_operand(operand);
+                }),
+
+                (Mnemonic::LAX, false) => Operand(Self::lax),
+                (Mnemonic::SAX, false) => Operand(Self::sax),
+                (Mnemonic::DCP, false) => Operand(Self::dcp),
+                (Mnemonic::ISB, false) => Operand(Self::isb),
+                (Mnemonic::SLO, false) => Operand(Self::slo),
+                (Mnemonic::RLA, false) => Operand(Self::rla),
+                (Mnemonic::SRE, false) => Operand(Self::sre),
+                (Mnemonic::RRA, false) => Operand(Self::rra),
+                (Mnemonic::ANC, false) => Operand(Self::anc),
+                (Mnemonic::ALR, false) => Operand(Self::alr),
+                (Mnemonic::ARR, false) => Operand(Self::arr),
+                (Mnemonic::AXS, false) => Operand(Self::axs),
+
+                _ => panic!("opcode table pairs an instruction with the wrong addressing mode"),
+            }
+        }
+
+        /// Services a latched NMI, or an IRQ while the I flag is clear.
+        fn poll_interrupts(&mut self) -> Option<Interrupt> {
+            let interrupt = if self.nmi_pending {
+                self.nmi_pending = false;
+                Interrupt::NMI
+            } else if (self.irq_line || self.bus.irq_line())
+                && !self.status.contains(CpuFlags::INTERRUPT_DISABLE)
+            {
+                Interrupt::IRQ
+            } else {
+                return None;
+            };
+
+            self.interrupt(interrupt.vector(), false);
+            self.cycles += 7;
+            Some(interrupt)
+        }
+
+        /// Pushes PC and status, masks IRQs and jumps through `vector`.
+        fn interrupt(&mut self, vector: u16, from_software: bool) {
+            self.stack_push_u16(self.program_counter);
+            self.stack_push(self.status.to_pushed_byte(from_software));
+            self.status.insert(CpuFlags::INTERRUPT_DISABLE);
+            self.program_counter = self.mem_read_u16(vector);
+        }
+
+        /// Lets the bus catch up on the cycles just spent, halts for any DMA
+        /// they started and samples the NMI line the bus drives.
+        fn tick_bus(&mut self, cycles: usize) {
+            self.bus.tick(cycles);
+            let stall = self.bus.take_stall_cycles(self.cycles);
+            if stall > 0 {
+                self.cycles += stall;
+                self.bus.tick(stall);
+            }
+            self.sample_nmi();
+        }
+
+        /// Drives the NMI input. NMI is edge-triggered: only the transition
+        /// from inactive to active latches an interrupt.
+        pub fn set_nmi_line(&mut self, active: bool) {
+            self.nmi_input = active;
+            self.sample_nmi();
+        }
+
+        /// The NMI input is wired-OR between `set_nmi_line` and
+        /// `Bus::nmi_line`; a rising edge of either latches an interrupt.
+        fn sample_nmi(&mut self) {
+            let active = self.nmi_input || self.bus.nmi_line();
+            if active && !self.nmi_line {
+                self.nmi_pending = true;
+            }
+            self.nmi_line = active;
+        }
+
+        /// Drives the IRQ input. IRQ is level-triggered and fires before every
+        /// instruction for as long as the line is held and I is clear. The
+        /// line is wired-OR with `Bus::irq_line`, so either source asserts it.
+        pub fn set_irq_line(&mut self, active: bool) {
+            self.irq_line = active;
+        }
+
+        fn unknown_opcode(
+            &mut self,
+            pc: u16,
+            opcode: u8,
+            interrupt: Option<Interrupt>,
+        ) -> Result<StepResult, CpuError> {
+            match self.unknown_opcode_policy {
+                UnknownOpcodePolicy::Halt => Err(CpuError::UnknownOpcode { pc, opcode, interrupt }),
+                UnknownOpcodePolicy::Nop => {
+                    self.program_counter = pc.wrapping_add(1);
+                    self.cycles += 2;
+                    Ok(StepResult {
+                        opcode,
+                        mnemonic: Mnemonic::NOP,
+                        mode: AddressingMode::NoneAddressing,
+                        operand_address: None,
+                        cycles: 0,
+                        halted: false,
+                        interrupt,
+                    })
+                }
+                UnknownOpcodePolicy::Jam => {
+                    self.jammed = true;
+                    Err(CpuError::Jammed { pc, opcode, interrupt })
+                }
+            }
+        }
+
+        /// Runs until the program halts on BRK.
+        pub fn run(&mut self) -> Result<(), CpuError> {
+            while !self.step()?.halted {}
+            Ok(())
+        }
+
+        /// Runs whole instructions until at least `cycles` cycles have elapsed
+        /// or the program halts. Returns the number of cycles actually run,
+        /// which can overshoot by up to one instruction.
+        pub fn run_for_cycles(&mut self, cycles: usize) -> Result<usize, CpuError> {
+            let target = self.cycles + cycles;
+            let start = self.cycles;
+            while self.cycles < target {
+                if self.step()?.halted {
+                    break;
+                }
+            }
+            Ok(self.cycles - start)
+        }
+
+        /// Steps until `predicate` holds after an instruction or the program
+        /// halts, returning the result of the last instruction executed.
+        pub fn run_until<F>(&mut self, mut predicate: F) -> Result<StepResult, CpuError>
+        where
+            F: FnMut(&CPU<B>) -> bool,
+        {
+            loop {
+                let result = self.step()?;
+                if result.halted || predicate(self) {
+                    return Ok(result);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bus::{Bus, FlatMemory};
+    use crate::cpu::{cpu::*, cpu_constants::*};
+
+    fn endify(lo: u8, hi: u8) -> u16 {
+        ((hi as u16) << 8) | lo as u16
+    }
+
+    fn dendify(bit: u16) -> (u8, u8) {
+        let hi = (bit >> 8) as u8;
+        let lo = (bit & 0xff) as u8;
+        (lo, hi)
+    }
+
+    #[test]
+    fn test_0xa9_lda_immediate_load_data() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x05, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0x05);
+        assert!(!cpu.status.contains(CpuFlags::ZERO));
+        assert!(!cpu.status.contains(CpuFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_0xa9_lda_zero_flag() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x00, BRK]).unwrap();
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+    }
+
+    #[test]
+    fn test_lda_zero_page_from_memory() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x55);
+
+        cpu.load_and_run(vec![LDA_ZP, 0x10, BRK]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn test_lda_zero_page_x_from_memory() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10 + 0x0F, 0x55);
+
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x0F, TAX, LDA_ZPX, 0x10, BRK]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn test_lda_absolute() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0xAAAA, 0x55);
+
+        let (lo, hi) = dendify(0xAAAA);
+
+        cpu.load_and_run(vec![LDA_ABS, lo, hi, BRK]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn test_lda_absolute_x() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0xAAAA + 0x80, 0x55);
+
+        let (lo, hi) = dendify(0xAAAA);
+
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x80, TAX, LDA_ABSX, lo, hi, BRK]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn test_lda_absolute_y() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0xAAAA + 0x80, 0x55);
+
+        let (lo, hi) = dendify(0xAAAA);
+
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x80, TAY, LDA_ABSY, lo, hi, BRK]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn test_lda_indirect_x() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x24, 0x74);
+        cpu.mem_write(0x25, 0x20);
+        cpu.mem_write(0x2074, 0x55);
+
+        cpu.load_and_run(vec![LDX_IMMEDIATE, 0x04, LDA_INDX, 0x20, BRK]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn test_lda_indirect_y() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x86, 0x28);
+        cpu.mem_write(0x87, 0x40);
+        cpu.mem_write(0x4038, 0x55);
+
+        cpu.load_and_run(vec![LDY_IMMEDIATE, 0x10, LDA_INDY, 0x86, BRK]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn test_0xaa_tax_move_a_to_x() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x0A, TAX, BRK]).unwrap();
+
+        assert_eq!(cpu.register_x, 0x0A)
+    }
+
+    #[test]
+    fn test_tay_sets_flags_from_y() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x80, TAY, BRK]).unwrap();
+
+        assert_eq!(cpu.register_y, 0x80);
+        assert!(cpu.status.contains(CpuFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_5_ops_working_together() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0xc0, TAX, INX, BRK]).unwrap();
+
+        assert_eq!(cpu.register_x, 0xc1)
+    }
+
+    #[test]
+    fn test_inx_overflow() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0xFF, TAX, INX, INX, BRK]).unwrap();
+        assert_eq!(cpu.register_x, 1)
+    }
+
+    #[test]
+    fn test_sta() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0xFF, STA_ZP, 0x16, BRK]).unwrap();
+        assert_eq!(cpu.mem_read(0x16), 0xFF);
+    }
+
+    #[test]
+    fn test_adc_sets_carry_and_overflow() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x50, ADC_IMMEDIATE, 0x50, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0xA0);
+        assert!(cpu.status.contains(CpuFlags::OVERFLOW));
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0xFF, ADC_IMMEDIATE, 0x02, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0x01);
+        assert!(!cpu.status.contains(CpuFlags::OVERFLOW));
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+    }
+
+    #[test]
+    fn test_adc_adds_carry_in() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![SEC, LDA_IMMEDIATE, 0x10, ADC_IMMEDIATE, 0x01, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0x12);
+    }
+
+    #[test]
+    fn test_sbc_borrows_when_carry_clear() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![SEC, LDA_IMMEDIATE, 0x10, SBC_IMMEDIATE, 0x01, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0x0F);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.load_and_run(vec![CLC, LDA_IMMEDIATE, 0x10, SBC_IMMEDIATE, 0x01, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0x0E);
+
+        cpu.load_and_run(vec![SEC, LDA_IMMEDIATE, 0x00, SBC_IMMEDIATE, 0x01, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0xFF);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+    }
+
+    #[test]
+    fn test_logical_operations() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0b1100, AND_IMMEDIATE, 0b1010, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0b1000);
+
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0b1100, ORA_IMMEDIATE, 0b1010, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0b1110);
+
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0b1100, EOR_IMMEDIATE, 0b1100, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0);
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+    }
+
+    #[test]
+    fn test_shifts_and_rotates_through_carry() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x81, ASL_ACC, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0x02);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x81, LSR_ACC, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0x40);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.load_and_run(vec![SEC, LDA_IMMEDIATE, 0x80, ROL_ACC, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0x01);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.load_and_run(vec![SEC, LDA_IMMEDIATE, 0x01, ROR_ACC, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0x80);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+    }
+
+    #[test]
+    fn test_inc_dec_memory() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0xFF);
+        cpu.load_and_run(vec![INC_ZP, 0x10, BRK]).unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x00);
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+
+        cpu.load_and_run(vec![DEC_ZP, 0x10, BRK]).unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0xFF);
+        assert!(cpu.status.contains(CpuFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_cmp_sets_carry_zero_negative() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x10, CMP_IMMEDIATE, 0x10, BRK]).unwrap();
+        assert!(cpu.status.contains(CpuFlags::CARRY | CpuFlags::ZERO));
+        assert!(!cpu.status.contains(CpuFlags::NEGATIVE));
+
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x10, CMP_IMMEDIATE, 0x20, BRK]).unwrap();
+        assert!(cpu.status.contains(CpuFlags::NEGATIVE));
+        assert!(!cpu.status.intersects(CpuFlags::CARRY | CpuFlags::ZERO));
+    }
+
+    #[test]
+    fn test_bit_copies_high_bits() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0b1100_0000);
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x01, BIT_ZP, 0x10, BRK]).unwrap();
+        assert!(cpu.status.contains(CpuFlags::NEGATIVE | CpuFlags::OVERFLOW | CpuFlags::ZERO));
+    }
+
+    #[test]
+    fn test_bne_loop() {
+        let mut cpu = CPU::new();
+        // LDX #$05; loop: INX; DEX; DEX; BNE loop
+        cpu.load_and_run(vec![LDX_IMMEDIATE, 0x05, INX, DEX, DEX, BNE, 0xFB, BRK]).unwrap();
+        assert_eq!(cpu.register_x, 0);
+    }
+
+    #[test]
+    fn test_cpx_counted_loop() {
+        let mut cpu = CPU::new();
+        // loop: INX; CPX #$08; BNE loop
+        cpu.load_and_run(vec![INX, CPX_IMMEDIATE, 0x08, BNE, 0xFB, BRK]).unwrap();
+        assert_eq!(cpu.register_x, 0x08);
+        assert!(cpu.status.contains(CpuFlags::CARRY | CpuFlags::ZERO));
+    }
+
+    #[test]
+    fn test_beq_not_taken() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x01, BEQ, 0x02, LDX_IMMEDIATE, 0x07, BRK]).unwrap();
+        assert_eq!(cpu.register_x, 0x07);
+    }
+
+    #[test]
+    fn test_jmp_absolute() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![JMP_ABS, 0x05, 0x80, LDA_IMMEDIATE, 0x01, LDX_IMMEDIATE, 0x02, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0);
+        assert_eq!(cpu.register_x, 0x02);
+    }
+
+    #[test]
+    fn test_jmp_indirect_page_wrap_bug() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x30FF, 0x05);
+        cpu.mem_write(0x3000, 0x80);
+        cpu.mem_write(0x3100, 0x90);
+        cpu.load_and_run(vec![JMP_IND, 0xFF, 0x30, LDA_IMMEDIATE, 0x01, LDX_IMMEDIATE, 0x02, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0);
+        assert_eq!(cpu.register_x, 0x02);
+    }
+
+    #[test]
+    fn test_jsr_rts() {
+        let mut cpu = CPU::new();
+        let (lo, hi) = dendify(0x8006);
+        cpu.load_and_run(vec![
+            JSR, lo, hi,
+            STX_ZP, 0x10,
+            BRK,
+            LDX_IMMEDIATE, 0x42,
+            RTS,
+        ]).unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x42);
+        assert_eq!(endify(cpu.mem_read(0x01FC), cpu.mem_read(0x01FD)), 0x8002);
+    }
+
+    #[test]
+    fn test_pha_pla() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x33, PHA, LDA_IMMEDIATE, 0x00, PLA, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0x33);
+        assert!(!cpu.status.contains(CpuFlags::ZERO));
+    }
+
+    #[test]
+    fn test_php_pushes_break_bits() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![SEC, PHP, BRK]).unwrap();
+        assert_eq!(cpu.mem_read(0x01FD), 0b0011_0101);
+    }
+
+    #[test]
+    fn test_reset_initializes_stack_pointer() {
+        let mut cpu = CPU::new();
+        cpu.stack_pointer = 0x10;
+        cpu.load(vec![BRK]);
+        cpu.reset();
+        assert_eq!(cpu.stack_pointer, 0xFD);
+    }
+
+    #[test]
+    fn test_stack_lives_in_page_one() {
+        let mut cpu = CPU::new();
+        cpu.stack_push_u16(0xBEEF);
+        assert_eq!(cpu.stack_pointer, 0xFB);
+        assert_eq!(cpu.mem_read(0x01FD), 0xBE);
+        assert_eq!(cpu.mem_read(0x01FC), 0xEF);
+        assert_eq!(cpu.stack_pop_u16(), 0xBEEF);
+        assert_eq!(cpu.stack_pointer, 0xFD);
+    }
+
+    #[test]
+    fn test_stack_pointer_wraps_within_page() {
+        let mut cpu = CPU::new();
+        cpu.stack_pointer = 0x00;
+        cpu.stack_push(0x42);
+        assert_eq!(cpu.stack_pointer, 0xFF);
+        assert_eq!(cpu.mem_read(0x0100), 0x42);
+        assert_eq!(cpu.mem_read(0x0000), 0x00);
+
+        assert_eq!(cpu.stack_pop(), 0x42);
+        assert_eq!(cpu.stack_pointer, 0x00);
+    }
+
+    #[test]
+    fn test_tsx_txs() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDX_IMMEDIATE, 0x80, TXS, PHA, TSX, BRK]).unwrap();
+        assert_eq!(cpu.register_x, 0x7F);
+        assert_eq!(cpu.stack_pointer, 0x7F);
+        assert!(!cpu.status.contains(CpuFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_reset_status_flags() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![BRK]).unwrap();
+        assert_eq!(cpu.status, CpuFlags::INTERRUPT_DISABLE | CpuFlags::UNUSED);
+    }
+
+    #[test]
+    fn test_pushed_status_byte() {
+        let flags = CpuFlags::CARRY | CpuFlags::NEGATIVE;
+        assert_eq!(flags.to_pushed_byte(true), 0b1011_0001);
+        assert_eq!(flags.to_pushed_byte(false), 0b1010_0001);
+    }
+
+    #[test]
+    fn test_plp_ignores_break_and_sets_unused() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0b1101_1011, PHA, PLP, BRK]).unwrap();
+        assert_eq!(cpu.status.bits(), 0b1110_1011);
+        assert_eq!(CpuFlags::from_pulled_byte(0x00).bits(), 0b0010_0000);
+    }
+
+    #[test]
+    fn test_cycles_accumulate_per_instruction() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x01, TAX, BRK]).unwrap();
+        // reset (7) + LDA (2) + TAX (2) + BRK (7)
+        assert_eq!(cpu.cycles, 18);
+    }
+
+    #[test]
+    fn test_page_cross_penalty_on_indexed_reads() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDX_IMMEDIATE, 0x01, LDA_ABSX, 0x00, 0x10, BRK]).unwrap();
+        assert_eq!(cpu.cycles, 7 + 2 + 4 + 7);
+
+        cpu.load_and_run(vec![LDX_IMMEDIATE, 0x01, LDA_ABSX, 0xFF, 0x10, BRK]).unwrap();
+        assert_eq!(cpu.cycles, 7 + 2 + 5 + 7);
+
+        cpu.mem_write(0x10, 0xFF);
+        cpu.mem_write(0x11, 0x20);
+        cpu.load_and_run(vec![LDY_IMMEDIATE, 0x01, LDA_INDY, 0x10, BRK]).unwrap();
+        assert_eq!(cpu.cycles, 7 + 2 + 6 + 7);
+    }
+
+    #[test]
+    fn test_no_page_cross_penalty_on_stores() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDX_IMMEDIATE, 0x01, STA_ABSX, 0xFF, 0x10, BRK]).unwrap();
+        assert_eq!(cpu.cycles, 7 + 2 + 5 + 7);
+    }
+
+    #[test]
+    fn test_branch_cycles() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x01, BEQ, 0x00, BRK]).unwrap();
+        assert_eq!(cpu.cycles, 7 + 2 + 2 + 7);
+
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x01, BNE, 0x00, BRK]).unwrap();
+        assert_eq!(cpu.cycles, 7 + 2 + 3 + 7);
+
+        // Lands on the zeroed page below $8000, which decodes as BRK.
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x00, BEQ, 0xF0, BRK]).unwrap();
+        assert_eq!(cpu.program_counter, 0x7FF5);
+        assert_eq!(cpu.cycles, 7 + 2 + 4 + 7);
+    }
+
+    #[test]
+    fn test_step_executes_one_instruction() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![LDX_IMMEDIATE, 0x01, LDA_ABSX, 0xFF, 0x10, BRK]);
+        cpu.reset();
+
+        let result = cpu.step().unwrap();
+        assert_eq!(result.opcode, LDX_IMMEDIATE);
+        assert_eq!(result.mnemonic, Mnemonic::LDX);
+        assert_eq!(result.mode, AddressingMode::Immediate);
+        assert_eq!(result.operand_address, Some(0x8001));
+        assert_eq!(result.cycles, 2);
+        assert!(!result.halted);
+        assert_eq!(cpu.program_counter, 0x8002);
+
+        let result = cpu.step().unwrap();
+        assert_eq!(result.mode, AddressingMode::Absolute_X);
+        assert_eq!(result.operand_address, Some(0x1100));
+        assert_eq!(result.cycles, 5);
+
+        let result = cpu.step().unwrap();
+        assert_eq!(result.mnemonic, Mnemonic::BRK);
+        assert_eq!(result.operand_address, None);
+        assert!(result.halted);
+    }
+
+    #[test]
+    fn test_step_trace_through_branch_onto_operand() {
+        // BNE $FF jumps to its own offset byte, which then runs as ISB $0200,X.
+        let mut cpu = CPU::new();
+        cpu.load(vec![LDX_IMMEDIATE, 0x01, BNE, 0xFF, 0x00, 0x02]);
+        cpu.reset();
+        cpu.step().unwrap();
+
+        let result = cpu.step().unwrap();
+        assert_eq!(result.mnemonic, Mnemonic::BNE);
+        assert_eq!(result.cycles, 3);
+        assert_eq!(cpu.program_counter, 0x8003);
+
+        let result = cpu.step().unwrap();
+        assert_eq!(result.opcode, 0xFF);
+        assert_eq!(result.mnemonic, Mnemonic::ISB);
+        assert_eq!(result.operand_address, Some(0x0201));
+        assert_eq!(result.cycles, 7);
+        assert_eq!(cpu.program_counter, 0x8006);
+    }
+
+    #[test]
+    fn test_run_for_cycles() {
+        let mut cpu = CPU::new();
+        // loop: INX; JMP loop
+        cpu.load(vec![INX, JMP_ABS, 0x00, 0x80]);
+        cpu.reset();
+
+        let ran = cpu.run_for_cycles(10).unwrap();
+        assert_eq!(ran, 10);
+        assert_eq!(cpu.register_x, 2);
+
+        let ran = cpu.run_for_cycles(1).unwrap();
+        assert_eq!(ran, 2);
+        assert_eq!(cpu.register_x, 3);
+    }
+
+    #[test]
+    fn test_run_for_cycles_stops_on_halt() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![INX, BRK]);
+        cpu.reset();
+
+        assert_eq!(cpu.run_for_cycles(1000).unwrap(), 9);
+    }
+
+    #[test]
+    fn test_run_until_predicate() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![INX, JMP_ABS, 0x00, 0x80]);
+        cpu.reset();
+
+        let result = cpu.run_until(|cpu| cpu.register_x == 5).unwrap();
+        assert_eq!(result.mnemonic, Mnemonic::INX);
+        assert_eq!(cpu.register_x, 5);
+        assert_eq!(cpu.program_counter, 0x8001);
+    }
+
+    #[test]
+    fn test_unknown_opcode_halts_with_error() {
+        let mut cpu = CPU::new();
+        let result = cpu.load_and_run(vec![INX, KIL, INX, BRK]);
+        let error = CpuError::UnknownOpcode { pc: 0x8001, opcode: KIL, interrupt: None };
+        assert_eq!(result, Err(error));
+        assert_eq!(cpu.register_x, 1);
+        assert_eq!(error.to_string(), "unknown opcode $02 at $8001");
+    }
+
+    #[test]
+    fn test_unknown_opcode_as_nop() {
+        let mut cpu = CPU::new();
+        cpu.unknown_opcode_policy = UnknownOpcodePolicy::Nop;
+        cpu.load_and_run(vec![INX, KIL, INX, BRK]).unwrap();
+        assert_eq!(cpu.register_x, 2);
+        assert_eq!(cpu.cycles, 7 + 2 + 2 + 2 + 7);
+    }
+
+    #[test]
+    fn test_unknown_opcode_jams_until_reset() {
+        let mut cpu = CPU::new();
+        cpu.unknown_opcode_policy = UnknownOpcodePolicy::Jam;
+        cpu.load(vec![KIL, INX, BRK]);
+        cpu.reset();
+
+        let jam = Err(CpuError::Jammed { pc: 0x8000, opcode: KIL, interrupt: None });
+        assert_eq!(cpu.step(), jam);
+        assert_eq!(cpu.step(), jam);
+        assert_eq!(cpu.program_counter, 0x8000);
+
+        cpu.reset();
+        cpu.mem_write(0x8000, INX);
+        cpu.run().unwrap();
+        assert_eq!(cpu.register_x, 2);
+    }
+
+    #[test]
+    fn test_opcode_table_matches_opcode_list() {
+        assert_eq!(OPCODES.iter().filter(|op| !op.unofficial).count(), 151);
+        assert_eq!(OPCODES.len(), 236);
+        assert_eq!(OPCODE_TABLE.iter().filter(|op| op.is_some()).count(), OPCODES.len());
+
+        for (byte, entry) in OPCODE_TABLE.iter().enumerate() {
+            if let Some(op) = entry {
+                assert_eq!(op.opcode as usize, byte);
+            }
+        }
+        assert_eq!(find_opcode_by_instruction(0xA9).unwrap().mnemonic, Mnemonic::LDA);
+        assert!(find_opcode_by_instruction(KIL).is_none());
+    }
+
+    #[test]
+    fn test_linear_scan_baseline_matches_step() {
+        // loop: INX; STA $0200,X; LSR A; SLO $10; CPX #$00; BNE loop; BRK
+        let program = vec![
+            INX, STA_ABSX, 0x00, 0x02, LSR_ACC, SLO_ZP, 0x10, CPX_IMMEDIATE, 0x00, BNE, 0xF5, BRK,
+        ];
+        let mut table = CPU::new();
+        let mut linear = CPU::new();
+        table.load_and_run(program.clone()).unwrap();
+        linear.load(program);
+        linear.reset();
+
+        let mut steps = 0;
+        while !linear.step_linear_scan().unwrap().halted {
+            steps += 1;
+        }
+        assert!(steps > 100);
+        assert_eq!(linear.cycles, table.cycles);
+        assert_eq!(linear.register_x, table.register_x);
+        assert_eq!(linear.program_counter, table.program_counter);
+    }
+
+    fn cpu_with_handler(vector: u16, handler: &[u8]) -> CPU {
+        let mut cpu = CPU::new();
+        // main: JMP main
+        cpu.load(vec![JMP_ABS, 0x00, 0x80]);
+        for (i, byte) in handler.iter().enumerate() {
+            cpu.mem_write(0x9000 + i as u16, *byte);
+        }
+        let (lo, hi) = dendify(0x9000);
+        cpu.mem_write(vector, lo);
+        cpu.mem_write(vector + 1, hi);
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn test_nmi_is_edge_triggered() {
+        let mut cpu = cpu_with_handler(0xFFFA, &[INX, RTI]);
+        cpu.step().unwrap();
+
+        cpu.set_nmi_line(true);
+        let result = cpu.step().unwrap();
+        assert_eq!(result.interrupt, Some(Interrupt::NMI));
+        assert_eq!(result.mnemonic, Mnemonic::INX);
+        assert_eq!(result.cycles, 7 + 2);
+        assert_eq!(cpu.register_x, 1);
+        assert!(cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+        assert_eq!(endify(cpu.mem_read(0x01FC), cpu.mem_read(0x01FD)), 0x8000);
+        assert_eq!(cpu.mem_read(0x01FB) & 0b0011_0000, 0b0010_0000);
+
+        let result = cpu.step().unwrap();
+        assert_eq!(result.mnemonic, Mnemonic::RTI);
+        assert_eq!(cpu.program_counter, 0x8000);
+        assert_eq!(cpu.stack_pointer, 0xFD);
+
+        // Holding the line does not retrigger.
+        cpu.set_nmi_line(true);
+        cpu.run_for_cycles(30).unwrap();
+        assert_eq!(cpu.register_x, 1);
+
+        cpu.set_nmi_line(false);
+        cpu.set_nmi_line(true);
+        cpu.step().unwrap();
+        assert_eq!(cpu.register_x, 2);
+    }
+
+    #[test]
+    fn test_irq_is_masked_by_interrupt_disable() {
+        let mut cpu = cpu_with_handler(0xFFFE, &[INX, RTI]);
+        cpu.set_irq_line(true);
+
+        let result = cpu.step().unwrap();
+        assert_eq!(result.interrupt, None);
+        assert_eq!(cpu.register_x, 0);
+
+        cpu.mem_write(0x8000, CLI);
+        cpu.mem_write(0x8001, JMP_ABS);
+        cpu.mem_write(0x8002, 0x01);
+        cpu.mem_write(0x8003, 0x80);
+        cpu.reset();
+        cpu.step().unwrap();
+
+        let result = cpu.step().unwrap();
+        assert_eq!(result.interrupt, Some(Interrupt::IRQ));
+        assert_eq!(cpu.register_x, 1);
+        assert_eq!(endify(cpu.mem_read(0x01FC), cpu.mem_read(0x01FD)), 0x8001);
+    }
+
+    #[test]
+    fn test_irq_is_level_triggered() {
+        let mut cpu = cpu_with_handler(0xFFFE, &[INX, RTI]);
+        cpu.mem_write(0x8000, CLI);
+        cpu.mem_write(0x8001, JMP_ABS);
+        cpu.mem_write(0x8002, 0x01);
+        cpu.mem_write(0x8003, 0x80);
+        cpu.reset();
+        cpu.step().unwrap();
+
+        cpu.set_irq_line(true);
+        cpu.run_until(|cpu| cpu.register_x == 3).unwrap();
+
+        cpu.set_irq_line(false);
+        cpu.run_for_cycles(50).unwrap();
+        assert_eq!(cpu.register_x, 3);
+    }
+
+    #[test]
+    fn test_nmi_takes_priority_over_irq() {
+        let mut cpu = cpu_with_handler(0xFFFA, &[INX, RTI]);
+        cpu.status.remove(CpuFlags::INTERRUPT_DISABLE);
+        cpu.set_irq_line(true);
+        cpu.set_nmi_line(true);
+
+        assert_eq!(cpu.step().unwrap().interrupt, Some(Interrupt::NMI));
+    }
+
+    #[test]
+    fn test_brk_as_software_interrupt() {
+        let mut cpu = cpu_with_handler(0xFFFE, &[LDX_IMMEDIATE, 0x42, RTI]);
+        cpu.brk_behavior = BrkBehavior::Interrupt;
+        cpu.mem_write(0x8000, BRK);
+        cpu.mem_write(0x8002, INX);
+        cpu.mem_write(0x8003, BRK);
+        cpu.reset();
+
+        let result = cpu.step().unwrap();
+        assert!(!result.halted);
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert_eq!(endify(cpu.mem_read(0x01FC), cpu.mem_read(0x01FD)), 0x8002);
+        assert_eq!(cpu.mem_read(0x01FB) & 0b0011_0000, 0b0011_0000);
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x8002);
+        cpu.step().unwrap();
+        assert_eq!(cpu.register_x, 0x43);
+    }
+
+    /// Flat memory that adds up the cycles it is ticked by.
+    struct TickingBus {
+        memory: FlatMemory,
+        ticked: usize,
+    }
+
+    impl Bus for TickingBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.memory.read(addr)
+        }
+
+        fn write(&mut self, addr: u16, data: u8) {
+            self.memory.write(addr, data);
+        }
+
+        fn tick(&mut self, cycles: usize) {
+            self.ticked += cycles;
+        }
+    }
+
+    #[test]
+    fn test_unknown_opcode_in_nmi_handler() {
+        for policy in [UnknownOpcodePolicy::Halt, UnknownOpcodePolicy::Jam] {
+            let mut cpu = CPU::with_bus(TickingBus { memory: FlatMemory::new(), ticked: 0 });
+            cpu.unknown_opcode_policy = policy;
+            cpu.load(vec![JMP_ABS, 0x00, 0x80]);
+            cpu.mem_write(0x9000, KIL);
+            cpu.mem_write(0xFFFA, 0x00);
+            cpu.mem_write(0xFFFB, 0x90);
+            cpu.reset();
+            let start = cpu.cycles;
+
+            cpu.set_nmi_line(true);
+            let error = cpu.step().unwrap_err();
+            let interrupt = Some(Interrupt::NMI);
+            assert_eq!(
+                error,
+                match policy {
+                    UnknownOpcodePolicy::Jam => CpuError::Jammed { pc: 0x9000, opcode: KIL, interrupt },
+                    _ => CpuError::UnknownOpcode { pc: 0x9000, opcode: KIL, interrupt },
+                }
+            );
+            assert_eq!(cpu.cycles - start, 7);
+            assert_eq!(cpu.bus.ticked, 7);
+            assert_eq!(endify(cpu.mem_read(0x01FC), cpu.mem_read(0x01FD)), 0x8000);
+        }
+        assert_eq!(
+            CpuError::UnknownOpcode { pc: 0x9000, opcode: KIL, interrupt: Some(Interrupt::NMI) }.to_string(),
+            "unknown opcode $02 at $9000 entering the NMI handler"
+        );
+    }
+
+    #[test]
+    fn test_unofficial_opcodes_are_flagged() {
+        assert!(!find_opcode_by_instruction(SBC_IMMEDIATE).unwrap().unofficial);
+        assert!(find_opcode_by_instruction(SBC_IMMEDIATE_UNOFFICIAL).unwrap().unofficial);
+        assert!(find_opcode_by_instruction(LAX_ZP).unwrap().unofficial);
+    }
+
+    #[test]
+    fn test_lax_sax() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x8F);
+        cpu.load_and_run(vec![LAX_ZP, 0x10, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0x8F);
+        assert_eq!(cpu.register_x, 0x8F);
+        assert!(cpu.status.contains(CpuFlags::NEGATIVE));
+
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0xF0, LDX_IMMEDIATE, 0x3C, SAX_ZP, 0x20, BRK]).unwrap();
+        assert_eq!(cpu.mem_read(0x20), 0x30);
+    }
+
+    #[test]
+    fn test_dcp_isb() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x06);
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x05, DCP_ZP, 0x10, BRK]).unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x05);
+        assert!(cpu.status.contains(CpuFlags::CARRY | CpuFlags::ZERO));
+
+        cpu.mem_write(0x10, 0x01);
+        cpu.load_and_run(vec![SEC, LDA_IMMEDIATE, 0x05, ISB_ZP, 0x10, BRK]).unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x02);
+        assert_eq!(cpu.register_a, 0x03);
+    }
+
+    #[test]
+    fn test_shift_combos() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x81);
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x01, SLO_ZP, 0x10, BRK]).unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x02);
+        assert_eq!(cpu.register_a, 0x03);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.mem_write(0x10, 0x81);
+        cpu.load_and_run(vec![SEC, LDA_IMMEDIATE, 0x0F, RLA_ZP, 0x10, BRK]).unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x03);
+        assert_eq!(cpu.register_a, 0x03);
+
+        cpu.mem_write(0x10, 0x03);
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0xFF, SRE_ZP, 0x10, BRK]).unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x01);
+        assert_eq!(cpu.register_a, 0xFE);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.mem_write(0x10, 0x02);
+        cpu.load_and_run(vec![CLC, LDA_IMMEDIATE, 0x10, RRA_ZP, 0x10, BRK]).unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x01);
+        assert_eq!(cpu.register_a, 0x11);
+    }
+
+    #[test]
+    fn test_immediate_combos() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0xF0, ANC_IMMEDIATE, 0x80, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0x80);
+        assert!(cpu.status.contains(CpuFlags::CARRY | CpuFlags::NEGATIVE));
+
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0xFF, ALR_IMMEDIATE, 0x03, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0x01);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.load_and_run(vec![SEC, LDA_IMMEDIATE, 0xFF, ARR_IMMEDIATE, 0x80, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0xC0);
+        assert!(cpu.status.contains(CpuFlags::CARRY | CpuFlags::OVERFLOW));
+
+        cpu.load_and_run(vec![LDA_IMMEDIATE, 0x0F, LDX_IMMEDIATE, 0x3C, AXS_IMMEDIATE, 0x04, BRK]).unwrap();
+        assert_eq!(cpu.register_x, 0x08);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.load_and_run(vec![SEC, LDA_IMMEDIATE, 0x05, SBC_IMMEDIATE_UNOFFICIAL, 0x02, BRK]).unwrap();
+        assert_eq!(cpu.register_a, 0x03);
+    }
+
+    #[test]
+    fn test_unofficial_nop_reads_operand() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![LDX_IMMEDIATE, 0x01, NOP_ABSX, 0xFF, 0x10, INX, BRK]).unwrap();
+        assert_eq!(cpu.register_x, 0x02);
+        assert_eq!(cpu.cycles, 7 + 2 + 5 + 2 + 7);
+    }
+
+    /// ROM at $8000 and a write-only port at $4000 that records what it sees.
+    struct PortBus {
+        rom: Vec<u8>,
+        port_writes: Vec<u8>,
+    }
+
+    impl Bus for PortBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            match addr {
+                0x8000..=0xFFFB => *self.rom.get((addr - 0x8000) as usize).unwrap_or(&0),
+                0xFFFC => 0x00,
+                0xFFFD => 0x80,
+                _ => 0,
+            }
+        }
+
+        fn write(&mut self, addr: u16, data: u8) {
+            if addr == 0x4000 {
+                self.port_writes.push(data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cpu_runs_on_custom_bus() {
+        let rom = vec![LDA_IMMEDIATE, 0x41, STA_ABSX, 0x00, 0x40, INX, BRK];
+        let mut cpu = CPU::with_bus(PortBus { rom, port_writes: vec![] });
+        cpu.reset();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.bus.port_writes, vec![0x41]);
+        assert_eq!(cpu.register_x, 1);
+    }
+
+    #[test]
+    fn test_control_flow_onto_own_operand() {
+        // BNE $FF targets its own offset byte, which is also where the PC
+        // points right after the opcode fetch.
+        let mut cpu = CPU::new();
+        cpu.load(vec![LDX_IMMEDIATE, 0x01, BNE, 0xFF]);
+        cpu.reset();
+        cpu.step().unwrap();
+        assert_eq!(cpu.step().unwrap().cycles, 3);
+        assert_eq!(cpu.program_counter, 0x8003);
+
+        cpu.mem_write(0x0200, JMP_ABS);
+        cpu.mem_write(0x0201, 0x01);
+        cpu.mem_write(0x0202, 0x02);
+        cpu.program_counter = 0x0200;
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0201);
+
+        // RTS to $0301 from an RTS at $0300.
+        cpu.mem_write(0x0300, RTS);
+        cpu.stack_push_u16(0x0300);
+        cpu.program_counter = 0x0300;
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0301);
+    }
+
+    #[test]
+    fn test_program_counter_wraps_past_ffff() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0xFFFE, LDA_IMMEDIATE);
+        cpu.mem_write(0xFFFF, 0x42);
+        cpu.program_counter = 0xFFFE;
+        cpu.step().unwrap();
+        assert_eq!(cpu.register_a, 0x42);
+        assert_eq!(cpu.program_counter, 0x0000);
+
+        // JSR at $FFFF takes its target from $0000-$0001 and returns to $0002.
+        cpu.mem_write(0xFFFF, JSR);
+        cpu.mem_write(0x0000, 0x00);
+        cpu.mem_write(0x0001, 0x06);
+        cpu.mem_write(0x0600, RTS);
+        cpu.program_counter = 0xFFFF;
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0600);
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0002);
+    }
+
+    /// Flat memory that counts every read.
+    struct CountingBus {
+        memory: FlatMemory,
+        reads: usize,
+    }
+
+    impl Bus for CountingBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.reads += 1;
+            self.memory.read(addr)
+        }
+
+        fn write(&mut self, addr: u16, data: u8) {
+            self.memory.write(addr, data);
+        }
+    }
+
+    #[test]
+    fn test_operand_address_is_resolved_once() {
+        let mut cpu = CPU::with_bus(CountingBus { memory: FlatMemory::new(), reads: 0 });
+        cpu.mem_write(0x0600, LDA_INDY);
+        cpu.mem_write(0x0601, 0x10);
+        cpu.mem_write(0x0010, 0x00);
+        cpu.mem_write(0x0011, 0x02);
+        cpu.mem_write(0x0200, 0x42);
+        cpu.program_counter = 0x0600;
+
+        cpu.step().unwrap();
+
+        // Opcode, pointer address, pointer low and high bytes, operand.
+        assert_eq!(cpu.bus.reads, 5);
+        assert_eq!(cpu.register_a, 0x42);
+    }
+
+    #[test]
+    fn test_read_modify_write_writes_twice() {
+        let rom = vec![INC_ABS, 0x00, 0x40, BRK];
+        let mut cpu = CPU::with_bus(PortBus { rom, port_writes: vec![] });
+        cpu.reset();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.bus.port_writes, vec![0x00, 0x01]);
+    }
+}
@@ -0,0 +1,57 @@
+use crate::palette::Palette;
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+/// One picture's worth of PPU output, stored as 6-bit palette indices.
+pub struct Frame {
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new() -> Self {
+        Frame {
+            data: vec![0; WIDTH * HEIGHT],
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, index: u8) {
+        self.data[y * WIDTH + x] = index;
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.data[y * WIDTH + x]
+    }
+
+    /// Converts the frame to packed 24-bit RGB, row by row.
+    pub fn to_rgb(&self, palette: &Palette) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(WIDTH * HEIGHT * 3);
+        for &index in &self.data {
+            let (r, g, b) = palette.rgb(index);
+            rgb.extend_from_slice(&[r, g, b]);
+        }
+        rgb
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_rgb_uses_palette() {
+        let mut frame = Frame::new();
+        frame.set_pixel(1, 0, 0x30);
+
+        let rgb = frame.to_rgb(&Palette::default());
+        assert_eq!(rgb.len(), WIDTH * HEIGHT * 3);
+        assert_eq!(&rgb[0..3], &[0x80, 0x80, 0x80]);
+        assert_eq!(&rgb[3..6], &[0xFF, 0xFF, 0xFF]);
+    }
+}
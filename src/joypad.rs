@@ -0,0 +1,162 @@
+bitflags! {
+    /// Buttons of a standard controller, in the order they are shifted out.
+    pub struct JoypadButton: u8 {
+        const RIGHT    = 0b1000_0000;
+        const LEFT     = 0b0100_0000;
+        const DOWN     = 0b0010_0000;
+        const UP       = 0b0001_0000;
+        const START    = 0b0000_1000;
+        const SELECT   = 0b0000_0100;
+        const BUTTON_B = 0b0000_0010;
+        const BUTTON_A = 0b0000_0001;
+    }
+}
+
+/// A standard controller: a parallel-in, serial-out shift register that
+/// latches the buttons while strobe is high.
+pub struct Joypad {
+    strobe: bool,
+    shift_register: u8,
+    button_status: JoypadButton,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            strobe: false,
+            shift_register: 0,
+            button_status: JoypadButton::empty(),
+        }
+    }
+
+    /// Bit 0 of a $4016 write drives strobe. The register is loaded while
+    /// strobe is high and keeps the last load once it falls.
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        self.shift_register = self.button_status.bits();
+    }
+
+    /// Shifts out the next latched button in bit 0. While strobe is high the
+    /// register keeps reloading, so every read returns the live state of A.
+    /// Ones shift in behind the buttons, so after all eight an official
+    /// controller returns 1.
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            self.shift_register = self.button_status.bits();
+            return self.shift_register & 1;
+        }
+        let response = self.shift_register & 1;
+        self.shift_register = (self.shift_register >> 1) | 0x80;
+        response
+    }
+
+    pub fn set_button_pressed_status(&mut self, button: JoypadButton, pressed: bool) {
+        self.button_status.set(button, pressed);
+    }
+
+    /// Replaces the state of every button, e.g. once per frame from host
+    /// input.
+    pub fn set_buttons(&mut self, buttons: JoypadButton) {
+        self.button_status = buttons;
+    }
+
+    pub fn buttons(&self) -> JoypadButton {
+        self.button_status
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strobe_mode() {
+        let mut joypad = Joypad::new();
+        joypad.write(1);
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_A, true);
+        for _ in 0..10 {
+            assert_eq!(joypad.read(), 1);
+        }
+    }
+
+    #[test]
+    fn test_strobe_mode_on_off() {
+        let mut joypad = Joypad::new();
+
+        joypad.set_button_pressed_status(JoypadButton::RIGHT, true);
+        joypad.set_button_pressed_status(JoypadButton::LEFT, true);
+        joypad.set_button_pressed_status(JoypadButton::SELECT, true);
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_B, true);
+        joypad.write(1);
+        joypad.write(0);
+
+        for _ in 0..=1 {
+            assert_eq!(joypad.read(), 0);
+            assert_eq!(joypad.read(), 1);
+            assert_eq!(joypad.read(), 1);
+            assert_eq!(joypad.read(), 0);
+            assert_eq!(joypad.read(), 0);
+            assert_eq!(joypad.read(), 0);
+            assert_eq!(joypad.read(), 1);
+            assert_eq!(joypad.read(), 1);
+
+            for _ in 0..10 {
+                assert_eq!(joypad.read(), 1);
+            }
+            joypad.write(1);
+            joypad.write(0);
+        }
+    }
+
+    #[test]
+    fn test_buttons_latched_when_strobe_falls() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(JoypadButton::BUTTON_A | JoypadButton::START);
+        joypad.write(1);
+        joypad.write(0);
+
+        assert_eq!(joypad.read(), 1);
+        // Changes after the latch are not seen until the next strobe.
+        joypad.set_buttons(JoypadButton::BUTTON_B | JoypadButton::SELECT);
+        assert_eq!(joypad.read(), 0);
+        assert_eq!(joypad.read(), 0);
+        joypad.set_buttons(JoypadButton::empty());
+        assert_eq!(joypad.read(), 1);
+        for _ in 4..8 {
+            assert_eq!(joypad.read(), 0);
+        }
+        assert_eq!(joypad.read(), 1);
+
+        joypad.write(1);
+        joypad.write(0);
+        for _ in 0..8 {
+            assert_eq!(joypad.read(), 0);
+        }
+    }
+
+    #[test]
+    fn test_strobe_high_reads_live_button_a() {
+        let mut joypad = Joypad::new();
+        joypad.write(1);
+        assert_eq!(joypad.read(), 0);
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_A, true);
+        assert_eq!(joypad.read(), 1);
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_A, false);
+        joypad.write(0);
+        assert_eq!(joypad.read(), 0);
+    }
+
+    #[test]
+    fn test_set_buttons_replaces_state() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JoypadButton::UP, true);
+        joypad.set_buttons(JoypadButton::START | JoypadButton::BUTTON_A);
+        assert_eq!(joypad.buttons(), JoypadButton::START | JoypadButton::BUTTON_A);
+    }
+}
@@ -0,0 +1,14 @@
+#[macro_use]
+extern crate bitflags;
+
+pub mod apu;
+pub mod audio;
+pub mod bus;
+pub mod cartridge;
+pub mod cpu;
+pub mod frame;
+pub mod joypad;
+pub mod mapper;
+pub mod palette;
+pub mod ppu;
+pub mod save;
@@ -1,9 +1,3 @@
-#[macro_use]
-extern crate lazy_static;
-
-
-pub mod cpu;
-
 fn main() {
     println!("Hello, world!");
 }
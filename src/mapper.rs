@@ -0,0 +1,1133 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cartridge::{Mirroring, Rom, RomError};
+
+const PRG_RAM_SIZE: usize = 0x2000;
+const CHR_RAM_SIZE: usize = 0x2000;
+/// Trainers load at $7000, 4KB into PRG-RAM.
+const TRAINER_OFFSET: usize = 0x1000;
+
+/// The cartridge board between the console and the ROM chips.
+///
+/// The CPU sees it at $4020-$FFFF and the PPU at $0000-$1FFF. The mapper
+/// decides which banks appear in those windows, reacts to register writes,
+/// and controls how the nametables are mirrored.
+pub trait Mapper {
+    /// Reads $4020-$FFFF. `None` means nothing on the board drives the bus.
+    fn cpu_read(&mut self, addr: u16) -> Option<u8>;
+    fn cpu_write(&mut self, addr: u16, data: u8);
+
+    /// A write on the cycle right after another write, as done by
+    /// read-modify-write instructions. Most boards treat it like any other.
+    fn cpu_write_consecutive(&mut self, addr: u16, data: u8) {
+        self.cpu_write(addr, data);
+    }
+
+    /// Reads the pattern tables at $0000-$1FFF.
+    fn ppu_read(&mut self, addr: u16) -> u8;
+    fn ppu_write(&mut self, addr: u16, data: u8);
+
+    /// A pattern fetch made while rendering, `ppu_cycle` dots after
+    /// power-on. Boards that watch the PPU address bus override it.
+    fn ppu_fetch(&mut self, addr: u16, _ppu_cycle: u64) -> u8 {
+        self.ppu_read(addr)
+    }
+
+    fn mirroring(&self) -> Mirroring;
+
+    /// Level of the board's /IRQ output.
+    fn irq_line(&self) -> bool {
+        false
+    }
+
+    /// The board's memory chips, e.g. to save battery-backed PRG-RAM.
+    fn memory(&self) -> &CartridgeMemory;
+    fn memory_mut(&mut self) -> &mut CartridgeMemory;
+}
+
+/// The CPU bus and the PPU both talk to the same cartridge.
+pub type SharedMapper = Rc<RefCell<dyn Mapper>>;
+
+/// Builds the mapper the header asks for.
+pub fn from_rom(rom: Rom) -> Result<SharedMapper, RomError> {
+    let number = rom.info.mapper;
+    let mirroring = rom.info.screen_mirroring;
+    // NES 2.0 submapper 4 marks boards with the older MMC3A.
+    let mmc3_revision = if rom.info.submapper == 4 {
+        Mmc3Revision::Nec
+    } else {
+        Mmc3Revision::Sharp
+    };
+    let memory = CartridgeMemory::new(rom);
+
+    let mapper: SharedMapper = match number {
+        0 => Rc::new(RefCell::new(Nrom::new(memory, mirroring))),
+        1 => Rc::new(RefCell::new(Mmc1::new(memory))),
+        2 => Rc::new(RefCell::new(UxRom::new(memory, mirroring))),
+        3 => Rc::new(RefCell::new(CnRom::new(memory, mirroring))),
+        4 => Rc::new(RefCell::new(Mmc3::new(memory, mirroring, mmc3_revision))),
+        7 => Rc::new(RefCell::new(AxRom::new(memory))),
+        _ => return Err(RomError::UnsupportedMapper(number)),
+    };
+    Ok(mapper)
+}
+
+/// The memory chips on a board. Banks are addressed in whatever size the
+/// mapper uses, and bank numbers wrap around the chip size the way they do
+/// on boards that leave the upper bank lines unconnected.
+pub struct CartridgeMemory {
+    pub prg_rom: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub chr_is_ram: bool,
+    pub prg_ram: Vec<u8>,
+}
+
+impl CartridgeMemory {
+    pub fn new(rom: Rom) -> Self {
+        let chr_is_ram = rom.chr_rom.is_empty();
+        let chr = if chr_is_ram {
+            vec![0; rom.info.chr_ram_size.max(CHR_RAM_SIZE)]
+        } else {
+            rom.chr_rom
+        };
+
+        let prg_ram_size = (rom.info.prg_ram_size + rom.info.prg_nvram_size).max(PRG_RAM_SIZE);
+        let mut prg_ram = vec![0; prg_ram_size];
+        if let Some(trainer) = &rom.trainer {
+            prg_ram[TRAINER_OFFSET..TRAINER_OFFSET + trainer.len()].copy_from_slice(trainer);
+        }
+
+        CartridgeMemory {
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            prg_ram,
+        }
+    }
+
+    pub fn prg_rom_banks(&self, bank_size: usize) -> usize {
+        (self.prg_rom.len() / bank_size).max(1)
+    }
+
+    pub fn read_prg_rom(&self, bank_size: usize, bank: usize, addr: u16) -> u8 {
+        let index = bank * bank_size + addr as usize % bank_size;
+        self.prg_rom[index % self.prg_rom.len()]
+    }
+
+    fn chr_index(&self, bank_size: usize, bank: usize, addr: u16) -> usize {
+        (bank * bank_size + addr as usize % bank_size) % self.chr.len()
+    }
+
+    pub fn read_chr(&self, bank_size: usize, bank: usize, addr: u16) -> u8 {
+        self.chr[self.chr_index(bank_size, bank, addr)]
+    }
+
+    /// CHR-ROM ignores writes.
+    pub fn write_chr(&mut self, bank_size: usize, bank: usize, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let index = self.chr_index(bank_size, bank, addr);
+            self.chr[index] = data;
+        }
+    }
+
+    /// Reads the 8KB PRG-RAM window at $6000-$7FFF from `bank`.
+    pub fn read_prg_ram(&self, bank: usize, addr: u16) -> u8 {
+        let index = bank * PRG_RAM_SIZE + (addr as usize - 0x6000);
+        self.prg_ram[index % self.prg_ram.len()]
+    }
+
+    pub fn write_prg_ram(&mut self, bank: usize, addr: u16, data: u8) {
+        let index = bank * PRG_RAM_SIZE + (addr as usize - 0x6000);
+        let len = self.prg_ram.len();
+        self.prg_ram[index % len] = data;
+    }
+}
+
+/// Mapper 0: 16KB or 32KB of PRG-ROM and 8KB of CHR, no bank switching.
+/// A 16KB PRG-ROM is mirrored into both halves of $8000-$FFFF.
+pub struct Nrom {
+    memory: CartridgeMemory,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(memory: CartridgeMemory, mirroring: Mirroring) -> Self {
+        Nrom { memory, mirroring }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => Some(self.memory.read_prg_ram(0, addr)),
+            0x8000..=0xFFFF => Some(self.memory.read_prg_rom(0x8000, 0, addr)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.memory.write_prg_ram(0, addr, data);
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.memory.read_chr(0x2000, 0, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.memory.write_chr(0x2000, 0, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+}
+
+/// Mapper 2: a switchable 16KB PRG bank at $8000 with the last bank fixed
+/// at $C000. Any write to $8000-$FFFF selects the bank.
+pub struct UxRom {
+    memory: CartridgeMemory,
+    mirroring: Mirroring,
+    prg_bank: usize,
+}
+
+impl UxRom {
+    pub fn new(memory: CartridgeMemory, mirroring: Mirroring) -> Self {
+        UxRom {
+            memory,
+            mirroring,
+            prg_bank: 0,
+        }
+    }
+}
+
+impl Mapper for UxRom {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => Some(self.memory.read_prg_ram(0, addr)),
+            0x8000..=0xBFFF => Some(self.memory.read_prg_rom(0x4000, self.prg_bank, addr)),
+            0xC000..=0xFFFF => {
+                let last_bank = self.memory.prg_rom_banks(0x4000) - 1;
+                Some(self.memory.read_prg_rom(0x4000, last_bank, addr))
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.memory.write_prg_ram(0, addr, data),
+            0x8000..=0xFFFF => self.prg_bank = data as usize,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.memory.read_chr(0x2000, 0, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.memory.write_chr(0x2000, 0, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+}
+
+/// Mapper 3: NROM-style PRG with a switchable 8KB CHR bank selected by
+/// any write to $8000-$FFFF.
+pub struct CnRom {
+    memory: CartridgeMemory,
+    mirroring: Mirroring,
+    chr_bank: usize,
+}
+
+impl CnRom {
+    pub fn new(memory: CartridgeMemory, mirroring: Mirroring) -> Self {
+        CnRom {
+            memory,
+            mirroring,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for CnRom {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => Some(self.memory.read_prg_ram(0, addr)),
+            0x8000..=0xFFFF => Some(self.memory.read_prg_rom(0x8000, 0, addr)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.memory.write_prg_ram(0, addr, data),
+            0x8000..=0xFFFF => self.chr_bank = data as usize,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.memory.read_chr(0x2000, self.chr_bank, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.memory.write_chr(0x2000, self.chr_bank, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+}
+
+/// Mapper 7: a switchable 32KB PRG bank and single-screen mirroring, both
+/// chosen by writes to $8000-$FFFF (`---M -PPP`).
+pub struct AxRom {
+    memory: CartridgeMemory,
+    prg_bank: usize,
+    mirroring: Mirroring,
+}
+
+impl AxRom {
+    pub fn new(memory: CartridgeMemory) -> Self {
+        AxRom {
+            memory,
+            prg_bank: 0,
+            mirroring: Mirroring::SingleScreenLower,
+        }
+    }
+}
+
+impl Mapper for AxRom {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => Some(self.memory.read_prg_ram(0, addr)),
+            0x8000..=0xFFFF => Some(self.memory.read_prg_rom(0x8000, self.prg_bank, addr)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.memory.write_prg_ram(0, addr, data),
+            0x8000..=0xFFFF => {
+                self.prg_bank = (data & 0b111) as usize;
+                self.mirroring = if data & 0b1_0000 != 0 {
+                    Mirroring::SingleScreenUpper
+                } else {
+                    Mirroring::SingleScreenLower
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.memory.read_chr(0x2000, 0, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.memory.write_chr(0x2000, 0, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+}
+
+/// Mapper 1, the MMC1 on SxROM boards.
+///
+/// Registers are loaded one bit at a time through a 5-bit shift register:
+/// five writes to $8000-$FFFF with the data in bit 0, LSB first. Address
+/// bits 13-14 of the fifth write pick the register:
+///
+/// ```text
+/// $8000 control   CPPMM  C: CHR mode (0: 8KB, 1: two 4KB banks)
+///                        P: PRG mode (0/1: 32KB, 2: first bank fixed at
+///                           $8000, 3: last bank fixed at $C000)
+///                        M: mirroring (0/1: single screen, 2: vertical,
+///                           3: horizontal)
+/// $A000 CHR bank 0
+/// $C000 CHR bank 1       (ignored in 8KB mode)
+/// $E000 PRG bank  RPPPP  R: PRG-RAM disable
+/// ```
+///
+/// A write with bit 7 set clears the shift register and locks the last
+/// PRG bank at $C000. Boards with more memory than the MMC1 can address
+/// borrow CHR bank 0 bits: SUROM uses bit 4 to select the 256KB half of a
+/// 512KB PRG-ROM, and SOROM/SXROM use bits 2-3 to bank PRG-RAM.
+pub struct Mmc1 {
+    memory: CartridgeMemory,
+    shift: u8,
+    shift_count: u8,
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    prg_bank: u8,
+}
+
+impl Mmc1 {
+    pub fn new(memory: CartridgeMemory) -> Self {
+        Mmc1 {
+            memory,
+            shift: 0,
+            shift_count: 0,
+            control: 0b0_1100,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+        }
+    }
+
+    fn write_serial(&mut self, addr: u16, data: u8) {
+        if data & 0b1000_0000 != 0 {
+            self.shift = 0;
+            self.shift_count = 0;
+            self.control |= 0b0_1100;
+            return;
+        }
+
+        self.shift |= (data & 1) << self.shift_count;
+        self.shift_count += 1;
+        if self.shift_count == 5 {
+            let value = self.shift;
+            match addr {
+                0x8000..=0x9FFF => self.control = value,
+                0xA000..=0xBFFF => self.chr_bank0 = value,
+                0xC000..=0xDFFF => self.chr_bank1 = value,
+                _ => self.prg_bank = value,
+            }
+            self.shift = 0;
+            self.shift_count = 0;
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0b1_0000 == 0
+    }
+
+    fn prg_ram_bank(&self) -> usize {
+        match self.memory.prg_ram.len() {
+            0x8000 => ((self.chr_bank0 >> 2) & 0b11) as usize,
+            0x4000 => ((self.chr_bank0 >> 3) & 1) as usize,
+            _ => 0,
+        }
+    }
+
+    /// The 16KB PRG bank mapped at `addr`.
+    fn prg_rom_bank(&self, addr: u16) -> usize {
+        let bank = (self.prg_bank & 0b1111) as usize;
+        let upper_half = addr >= 0xC000;
+        let bank = match (self.control >> 2) & 0b11 {
+            0 | 1 => (bank & !1) | upper_half as usize,
+            2 if upper_half => bank,
+            2 => 0,
+            _ if upper_half => 0b1111,
+            _ => bank,
+        };
+
+        // SUROM: 512KB of PRG-ROM in two 256KB halves.
+        if self.memory.prg_rom.len() > 0x40000 {
+            bank | (self.chr_bank0 & 0b1_0000) as usize
+        } else {
+            bank
+        }
+    }
+
+    fn chr_bank(&self, addr: u16) -> (usize, usize) {
+        if self.control & 0b1_0000 == 0 {
+            (0x2000, (self.chr_bank0 >> 1) as usize)
+        } else if addr < 0x1000 {
+            (0x1000, self.chr_bank0 as usize)
+        } else {
+            (0x1000, self.chr_bank1 as usize)
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                Some(self.memory.read_prg_ram(self.prg_ram_bank(), addr))
+            }
+            0x8000..=0xFFFF => {
+                Some(self.memory.read_prg_rom(0x4000, self.prg_rom_bank(addr), addr))
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.memory.write_prg_ram(self.prg_ram_bank(), addr, data)
+            }
+            0x8000..=0xFFFF => self.write_serial(addr, data),
+            _ => {}
+        }
+    }
+
+    /// The MMC1 only takes the first of two back-to-back serial writes.
+    fn cpu_write_consecutive(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            self.cpu_write(addr, data);
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let (bank_size, bank) = self.chr_bank(addr);
+        self.memory.read_chr(bank_size, bank, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let (bank_size, bank) = self.chr_bank(addr);
+        self.memory.write_chr(bank_size, bank, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+}
+
+/// PPU dots A12 has to stay low before a rise clocks the MMC3's counter.
+/// The chip filters A12 with M2, which needs about three CPU cycles; this
+/// skips the rises between sprite fetches and counts once per scanline.
+const MMC3_A12_FILTER_DOTS: u64 = 10;
+
+/// Which MMC3 a board carries. They differ only in when the scanline
+/// counter raises IRQ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mmc3Revision {
+    /// MMC3B/C: IRQ on every clock that leaves the counter at 0, so a
+    /// latch of 0 fires on every scanline.
+    Sharp,
+    /// MMC3A: IRQ only when the counter decrements to 0 or is reloaded
+    /// after a $C001 write, so a latch of 0 fires once.
+    Nec,
+}
+
+/// Mapper 4, the MMC3 on TxROM boards.
+///
+/// ```text
+/// $8000 even  bank select  CP.. .RRR  C: CHR A12 inversion, P: PRG mode,
+///                                     R: register for the next $8001
+/// $8001 odd   bank data    R0-R1: 2KB CHR, R2-R5: 1KB CHR, R6-R7: 8KB PRG
+/// $A000 even  mirroring    0: vertical, 1: horizontal
+/// $A001 odd   PRG-RAM      EW.. ....  E: enable, W: deny writes
+/// $C000 even  IRQ latch
+/// $C001 odd   IRQ reload
+/// $E000 even  IRQ disable and acknowledge
+/// $E001 odd   IRQ enable
+/// ```
+///
+/// PRG mode 0 maps R6 at $8000, R7 at $A000 and the last two banks at
+/// $C000; mode 1 swaps $8000 and $C000. CHR inversion swaps the 2KB and
+/// 1KB halves of the pattern tables.
+///
+/// The scanline counter clocks on rising edges of PPU A12 after it has
+/// been low for a while. With backgrounds at $0000 and sprites at $1000,
+/// that happens once per rendered line during the sprite fetches.
+pub struct Mmc3 {
+    memory: CartridgeMemory,
+    revision: Mmc3Revision,
+    bank_select: u8,
+    registers: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    prg_ram_writable: bool,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    /// PPU dot when A12 last went low, or `None` while it is high.
+    a12_low_since: Option<u64>,
+}
+
+impl Mmc3 {
+    pub fn new(memory: CartridgeMemory, mirroring: Mirroring, revision: Mmc3Revision) -> Self {
+        Mmc3 {
+            memory,
+            revision,
+            bank_select: 0,
+            registers: [0; 8],
+            mirroring,
+            prg_ram_enabled: true,
+            prg_ram_writable: true,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12_low_since: Some(0),
+        }
+    }
+
+    /// The 8KB PRG bank mapped at `addr`.
+    fn prg_rom_bank(&self, addr: u16) -> usize {
+        let banks = self.memory.prg_rom_banks(0x2000);
+        let last = banks - 1;
+        // The second-to-last bank wraps around like any other bank number,
+        // so an 8KB image mirrors its only bank there.
+        let second_last = (last + banks - 1) % banks;
+        let swapped = self.bank_select & 0b0100_0000 != 0;
+        match (addr >> 13) & 0b11 {
+            0 if swapped => second_last,
+            0 => self.registers[6] as usize,
+            1 => self.registers[7] as usize,
+            2 if swapped => self.registers[6] as usize,
+            2 => second_last,
+            _ => last,
+        }
+    }
+
+    /// The 1KB CHR bank mapped at `addr`.
+    fn chr_bank(&self, addr: u16) -> usize {
+        let addr = if self.bank_select & 0b1000_0000 != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+        let slot = (addr as usize / 0x400) & 0b111;
+        match slot {
+            0..=3 => (self.registers[slot / 2] & 0xFE) as usize | (slot & 1),
+            _ => self.registers[slot - 2] as usize,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        let even = addr & 1 == 0;
+        match (addr, even) {
+            (0x8000..=0x9FFF, true) => self.bank_select = data,
+            (0x8000..=0x9FFF, false) => {
+                let register = (self.bank_select & 0b111) as usize;
+                // PRG banks only have six bits.
+                self.registers[register] = if register >= 6 { data & 0x3F } else { data };
+            }
+            (0xA000..=0xBFFF, true) => {
+                // Four-screen boards wire their own nametable RAM.
+                if self.mirroring != Mirroring::FourScreen {
+                    self.mirroring = if data & 1 == 0 {
+                        Mirroring::Vertical
+                    } else {
+                        Mirroring::Horizontal
+                    };
+                }
+            }
+            (0xA000..=0xBFFF, false) => {
+                self.prg_ram_enabled = data & 0b1000_0000 != 0;
+                self.prg_ram_writable = data & 0b0100_0000 == 0;
+            }
+            (0xC000..=0xDFFF, true) => self.irq_latch = data,
+            (0xC000..=0xDFFF, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (_, true) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (_, false) => self.irq_enabled = true,
+        }
+    }
+
+    fn watch_a12(&mut self, addr: u16, ppu_cycle: u64) {
+        if addr & 0x1000 == 0 {
+            self.a12_low_since.get_or_insert(ppu_cycle);
+        } else if let Some(since) = self.a12_low_since.take() {
+            if ppu_cycle - since >= MMC3_A12_FILTER_DOTS {
+                self.clock_irq_counter();
+            }
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        let reloaded = self.irq_reload;
+        let was_zero = self.irq_counter == 0;
+        if was_zero || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        let fire = match self.revision {
+            Mmc3Revision::Sharp => self.irq_counter == 0,
+            Mmc3Revision::Nec => self.irq_counter == 0 && (!was_zero || reloaded),
+        };
+        if fire && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled => Some(self.memory.read_prg_ram(0, addr)),
+            0x8000..=0xFFFF => {
+                Some(self.memory.read_prg_rom(0x2000, self.prg_rom_bank(addr), addr))
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled && self.prg_ram_writable => {
+                self.memory.write_prg_ram(0, addr, data)
+            }
+            0x8000..=0xFFFF => self.write_register(addr, data),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.memory.read_chr(0x400, self.chr_bank(addr), addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.memory.write_chr(0x400, self.chr_bank(addr), addr, data);
+    }
+
+    fn ppu_fetch(&mut self, addr: u16, ppu_cycle: u64) -> u8 {
+        self.watch_a12(addr, ppu_cycle);
+        self.ppu_read(addr)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq_line(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn memory(&self) -> &CartridgeMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut CartridgeMemory {
+        &mut self.memory
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::cartridge::test::{create_rom, TestRom};
+    use crate::cartridge::{CHR_ROM_PAGE_SIZE, PRG_ROM_PAGE_SIZE};
+
+    /// An iNES image for `mapper` whose every 16KB PRG and 8KB CHR bank is
+    /// filled with its own bank number. Zero CHR banks means CHR-RAM.
+    pub fn banked_rom(mapper: u8, prg_banks: usize, chr_banks: usize) -> Rom {
+        let prg_rom = (0..prg_banks)
+            .flat_map(|bank| vec![bank as u8; PRG_ROM_PAGE_SIZE])
+            .collect();
+        let chr_rom = (0..chr_banks)
+            .flat_map(|bank| vec![bank as u8; CHR_ROM_PAGE_SIZE])
+            .collect();
+
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, prg_banks as u8, chr_banks as u8, (mapper & 0x0F) << 4,
+                mapper & 0xF0, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom,
+            chr_rom,
+        });
+        Rom::new(&raw).unwrap()
+    }
+
+    #[test]
+    fn test_nrom_mirrors_16kb_prg() {
+        let mut rom = banked_rom(0, 1, 1);
+        rom.prg_rom[0] = 0xAA;
+        rom.prg_rom[0x3FFF] = 0xBB;
+        let mapper = from_rom(rom).unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        assert_eq!(mapper.cpu_read(0x8000), Some(0xAA));
+        assert_eq!(mapper.cpu_read(0xC000), Some(0xAA));
+        assert_eq!(mapper.cpu_read(0xBFFF), Some(0xBB));
+        assert_eq!(mapper.cpu_read(0xFFFF), Some(0xBB));
+    }
+
+    #[test]
+    fn test_nrom_prg_ram_and_open_expansion_area() {
+        let mapper = from_rom(banked_rom(0, 2, 1)).unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        mapper.cpu_write(0x6123, 0x42);
+        assert_eq!(mapper.cpu_read(0x6123), Some(0x42));
+        assert_eq!(mapper.cpu_read(0x5000), None);
+        assert_eq!(mapper.cpu_read(0xC000), Some(1));
+    }
+
+    #[test]
+    fn test_chr_rom_is_read_only_and_chr_ram_is_writable() {
+        let mapper = from_rom(banked_rom(0, 1, 1)).unwrap();
+        mapper.borrow_mut().ppu_write(0x0010, 0x66);
+        assert_eq!(mapper.borrow_mut().ppu_read(0x0010), 0);
+
+        let mapper = from_rom(banked_rom(0, 1, 0)).unwrap();
+        mapper.borrow_mut().ppu_write(0x1FFF, 0x66);
+        assert_eq!(mapper.borrow_mut().ppu_read(0x1FFF), 0x66);
+    }
+
+    #[test]
+    fn test_uxrom_switches_lower_bank_and_fixes_last() {
+        let mapper = from_rom(banked_rom(2, 8, 0)).unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        assert_eq!(mapper.cpu_read(0x8000), Some(0));
+        assert_eq!(mapper.cpu_read(0xC000), Some(7));
+
+        mapper.cpu_write(0x8000, 5);
+        assert_eq!(mapper.cpu_read(0xBFFF), Some(5));
+        assert_eq!(mapper.cpu_read(0xFFFF), Some(7));
+
+        // Bank numbers wrap around the ROM size.
+        mapper.cpu_write(0xFFFF, 9);
+        assert_eq!(mapper.cpu_read(0x8000), Some(1));
+    }
+
+    #[test]
+    fn test_cnrom_switches_chr_bank() {
+        let mapper = from_rom(banked_rom(3, 2, 4)).unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        assert_eq!(mapper.ppu_read(0x0000), 0);
+        mapper.cpu_write(0x8000, 2);
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+        assert_eq!(mapper.ppu_read(0x1FFF), 2);
+        assert_eq!(mapper.cpu_read(0xC000), Some(1));
+    }
+
+    #[test]
+    fn test_axrom_switches_32kb_bank_and_single_screen() {
+        let mapper = from_rom(banked_rom(7, 8, 0)).unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0));
+
+        mapper.cpu_write(0x8000, 0b1_0011);
+        assert_eq!(mapper.cpu_read(0x8000), Some(6));
+        assert_eq!(mapper.cpu_read(0xFFFF), Some(7));
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    fn write_mmc1(mapper: &mut dyn Mapper, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mapper.cpu_write(addr, (value >> bit) & 1);
+        }
+    }
+
+    #[test]
+    fn test_mmc1_serial_load_and_reset() {
+        let mapper = from_rom(banked_rom(1, 8, 0)).unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        // Power-on: last bank fixed at $C000.
+        assert_eq!(mapper.cpu_read(0xC000), Some(7));
+        write_mmc1(&mut *mapper, 0xE000, 3);
+        assert_eq!(mapper.cpu_read(0x8000), Some(3));
+
+        // A reset mid-load throws away the bits shifted in so far.
+        mapper.cpu_write(0xE000, 1);
+        mapper.cpu_write(0xE000, 1);
+        mapper.cpu_write(0x8000, 0x80);
+        write_mmc1(&mut *mapper, 0xE000, 5);
+        assert_eq!(mapper.cpu_read(0x8000), Some(5));
+    }
+
+    #[test]
+    fn test_mmc1_ignores_consecutive_serial_writes() {
+        let mapper = from_rom(banked_rom(1, 8, 0)).unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        for _ in 0..5 {
+            mapper.cpu_write(0xE000, 1);
+            mapper.cpu_write_consecutive(0xE000, 0);
+        }
+        assert_eq!(mapper.cpu_read(0x8000), Some(7));
+    }
+
+    #[test]
+    fn test_mmc1_prg_modes() {
+        let mapper = from_rom(banked_rom(1, 8, 0)).unwrap();
+        let mut mapper = mapper.borrow_mut();
+        write_mmc1(&mut *mapper, 0xE000, 5);
+
+        // Mode 2: first bank fixed at $8000, switchable at $C000.
+        write_mmc1(&mut *mapper, 0x8000, 0b0_1000);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0));
+        assert_eq!(mapper.cpu_read(0xC000), Some(5));
+
+        // 32KB mode ignores the low bit of the bank number.
+        write_mmc1(&mut *mapper, 0x8000, 0b0_0000);
+        assert_eq!(mapper.cpu_read(0x8000), Some(4));
+        assert_eq!(mapper.cpu_read(0xC000), Some(5));
+    }
+
+    #[test]
+    fn test_mmc1_chr_modes_and_mirroring() {
+        let mapper = from_rom(banked_rom(1, 2, 4)).unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        // 8KB mode: CHR bank 0 counts 4KB units with the low bit ignored.
+        write_mmc1(&mut *mapper, 0x8000, 0b0_1110);
+        write_mmc1(&mut *mapper, 0xA000, 5);
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+        assert_eq!(mapper.ppu_read(0x1000), 2);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+
+        // 4KB mode: each half is banked separately.
+        write_mmc1(&mut *mapper, 0x8000, 0b1_0001);
+        write_mmc1(&mut *mapper, 0xC000, 2);
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+        assert_eq!(mapper.ppu_read(0x1000), 1);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_mmc1_prg_ram_disable() {
+        let mapper = from_rom(banked_rom(1, 2, 0)).unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        mapper.cpu_write(0x6000, 0x42);
+        write_mmc1(&mut *mapper, 0xE000, 0b1_0000);
+        assert_eq!(mapper.cpu_read(0x6000), None);
+        mapper.cpu_write(0x6000, 0x99);
+
+        write_mmc1(&mut *mapper, 0xE000, 0);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x42));
+    }
+
+    #[test]
+    fn test_mmc1_surom_selects_256kb_half() {
+        let mapper = from_rom(banked_rom(1, 32, 0)).unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        assert_eq!(mapper.cpu_read(0xC000), Some(15));
+        write_mmc1(&mut *mapper, 0xA000, 0b1_0000);
+        write_mmc1(&mut *mapper, 0xE000, 2);
+        assert_eq!(mapper.cpu_read(0x8000), Some(18));
+        assert_eq!(mapper.cpu_read(0xC000), Some(31));
+    }
+
+    /// An MMC3 with 256KB of PRG-ROM and CHR-ROM, where every 8KB PRG
+    /// bank and 1KB CHR bank holds its own bank number.
+    fn new_mmc3(revision: Mmc3Revision) -> Mmc3 {
+        let mut rom = banked_rom(4, 16, 32);
+        for (bank, chunk) in rom.prg_rom.chunks_mut(0x2000).enumerate() {
+            chunk.fill(bank as u8);
+        }
+        for (bank, chunk) in rom.chr_rom.chunks_mut(0x400).enumerate() {
+            chunk.fill(bank as u8);
+        }
+        Mmc3::new(CartridgeMemory::new(rom), Mirroring::Vertical, revision)
+    }
+
+    /// A rise of A12 after it has been low long enough to count.
+    fn clock_scanline(mapper: &mut Mmc3, ppu_cycle: &mut u64) {
+        mapper.ppu_fetch(0x0000, *ppu_cycle);
+        *ppu_cycle += 341;
+        mapper.ppu_fetch(0x1000, *ppu_cycle);
+    }
+
+    #[test]
+    fn test_mmc3_prg_banking() {
+        let mut mapper = new_mmc3(Mmc3Revision::Sharp);
+        mapper.cpu_write(0x8000, 6);
+        mapper.cpu_write(0x8001, 3);
+        mapper.cpu_write(0x8000, 7);
+        mapper.cpu_write(0x8001, 5);
+
+        assert_eq!(mapper.cpu_read(0x8000), Some(3));
+        assert_eq!(mapper.cpu_read(0xA000), Some(5));
+        assert_eq!(mapper.cpu_read(0xC000), Some(30));
+        assert_eq!(mapper.cpu_read(0xE000), Some(31));
+
+        // PRG mode 1 swaps $8000 and $C000.
+        mapper.cpu_write(0x8000, 0b0100_0000);
+        assert_eq!(mapper.cpu_read(0x8000), Some(30));
+        assert_eq!(mapper.cpu_read(0xC000), Some(3));
+        assert_eq!(mapper.cpu_read(0xE000), Some(31));
+    }
+
+    #[test]
+    fn test_mmc3_prg_banking_with_single_8kb_bank() {
+        let mut rom = banked_rom(4, 1, 8);
+        rom.prg_rom.truncate(0x2000);
+        rom.prg_rom.fill(0x42);
+        let mut mapper = Mmc3::new(CartridgeMemory::new(rom), Mirroring::Vertical, Mmc3Revision::Sharp);
+
+        for mode in [0, 0b0100_0000] {
+            mapper.cpu_write(0x8000, mode);
+            for addr in [0x8000, 0xA000, 0xC000, 0xE000] {
+                assert_eq!(mapper.cpu_read(addr), Some(0x42));
+            }
+        }
+    }
+
+    #[test]
+    fn test_mmc3_chr_banking_and_inversion() {
+        let mut mapper = new_mmc3(Mmc3Revision::Sharp);
+        for (register, bank) in [9, 20, 1, 2, 3, 4].into_iter().enumerate() {
+            mapper.cpu_write(0x8000, register as u8);
+            mapper.cpu_write(0x8001, bank);
+        }
+
+        // 2KB banks ignore their low bit.
+        assert_eq!(mapper.ppu_read(0x0000), 8);
+        assert_eq!(mapper.ppu_read(0x0400), 9);
+        assert_eq!(mapper.ppu_read(0x0800), 20);
+        assert_eq!(mapper.ppu_read(0x0C00), 21);
+        assert_eq!(mapper.ppu_read(0x1000), 1);
+        assert_eq!(mapper.ppu_read(0x1C00), 4);
+
+        mapper.cpu_write(0x8000, 0b1000_0000);
+        assert_eq!(mapper.ppu_read(0x0000), 1);
+        assert_eq!(mapper.ppu_read(0x0C00), 4);
+        assert_eq!(mapper.ppu_read(0x1000), 8);
+        assert_eq!(mapper.ppu_read(0x1C00), 21);
+    }
+
+    #[test]
+    fn test_mmc3_mirroring_and_prg_ram_protect() {
+        let mut mapper = new_mmc3(Mmc3Revision::Sharp);
+        mapper.cpu_write(0xA000, 1);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+
+        mapper.cpu_write(0x6000, 0x11);
+        mapper.cpu_write(0xA001, 0b1100_0000);
+        mapper.cpu_write(0x6000, 0x22);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x11));
+        mapper.cpu_write(0xA001, 0);
+        assert_eq!(mapper.cpu_read(0x6000), None);
+
+        let mut rom = banked_rom(4, 2, 1);
+        rom.info.screen_mirroring = Mirroring::FourScreen;
+        let mapper = from_rom(rom).unwrap();
+        mapper.borrow_mut().cpu_write(0xA000, 1);
+        assert_eq!(mapper.borrow().mirroring(), Mirroring::FourScreen);
+    }
+
+    #[test]
+    fn test_mmc3_scanline_irq() {
+        let mut mapper = new_mmc3(Mmc3Revision::Sharp);
+        let mut ppu_cycle = 0;
+        mapper.cpu_write(0xC000, 2);
+        mapper.cpu_write(0xC001, 0);
+        mapper.cpu_write(0xE001, 0);
+
+        // Reload to 2, then 1, then 0.
+        clock_scanline(&mut mapper, &mut ppu_cycle);
+        clock_scanline(&mut mapper, &mut ppu_cycle);
+        assert!(!mapper.irq_line());
+        clock_scanline(&mut mapper, &mut ppu_cycle);
+        assert!(mapper.irq_line());
+
+        mapper.cpu_write(0xE000, 0);
+        assert!(!mapper.irq_line());
+    }
+
+    #[test]
+    fn test_mmc3_filters_short_a12_pulses() {
+        let mut mapper = new_mmc3(Mmc3Revision::Sharp);
+        mapper.cpu_write(0xC000, 5);
+        mapper.cpu_write(0xC001, 0);
+
+        mapper.ppu_fetch(0x0000, 100);
+        mapper.ppu_fetch(0x1000, 200);
+        assert_eq!(mapper.irq_counter, 5);
+
+        // Sprite fetches toggle A12 every few dots; only the first rise
+        // after the background fetches counts.
+        for dot in 201..210 {
+            mapper.ppu_fetch(if dot % 2 == 0 { 0x1000 } else { 0x0000 }, dot);
+        }
+        assert_eq!(mapper.irq_counter, 5);
+    }
+
+    #[test]
+    fn test_mmc3_revisions_differ_on_zero_latch() {
+        for (revision, fires_again) in [(Mmc3Revision::Sharp, true), (Mmc3Revision::Nec, false)] {
+            let mut mapper = new_mmc3(revision);
+            let mut ppu_cycle = 0;
+            mapper.cpu_write(0xC000, 0);
+            mapper.cpu_write(0xC001, 0);
+            mapper.cpu_write(0xE001, 0);
+
+            clock_scanline(&mut mapper, &mut ppu_cycle);
+            assert!(mapper.irq_line());
+
+            mapper.cpu_write(0xE000, 0);
+            mapper.cpu_write(0xE001, 0);
+            clock_scanline(&mut mapper, &mut ppu_cycle);
+            assert_eq!(mapper.irq_line(), fires_again);
+        }
+    }
+
+    #[test]
+    fn test_unsupported_mapper() {
+        assert!(matches!(
+            from_rom(banked_rom(99, 1, 1)),
+            Err(RomError::UnsupportedMapper(99))
+        ));
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Number of colours the PPU can output.
+pub const PALETTE_SIZE: usize = 64;
+
+/// A commonly used 2C02 palette, in RGB.
+#[rustfmt::skip]
+pub static SYSTEM_PALETTE: [(u8, u8, u8); PALETTE_SIZE] = [
+    (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96),
+    (0xA1, 0x00, 0x5E), (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00),
+    (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00), (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E),
+    (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05), (0x05, 0x05, 0x05),
+    (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
+    (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00),
+    (0xC4, 0x62, 0x00), (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55),
+    (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21), (0x09, 0x09, 0x09), (0x09, 0x09, 0x09),
+    (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF), (0xD4, 0x80, 0xFF),
+    (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
+    (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4),
+    (0x05, 0xFB, 0xFF), (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D),
+    (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF), (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB),
+    (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0), (0xFF, 0xEF, 0xA6),
+    (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
+    (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11),
+];
+
+#[derive(Debug)]
+pub enum PaletteError {
+    Io(io::Error),
+    /// A .pal file must hold at least 64 RGB triples.
+    TooShort { actual: usize },
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::Io(err) => write!(f, "failed to read palette: {}", err),
+            PaletteError::TooShort { actual } => write!(
+                f,
+                "palette is {} bytes, expected at least {}",
+                actual,
+                PALETTE_SIZE * 3
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+impl From<io::Error> for PaletteError {
+    fn from(err: io::Error) -> Self {
+        PaletteError::Io(err)
+    }
+}
+
+/// Maps the PPU's 6-bit colour indices to RGB.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: [(u8, u8, u8); PALETTE_SIZE],
+}
+
+impl Palette {
+    /// Parses a .pal file: consecutive RGB triples for colours $00-$3F.
+    /// Files that also carry the emphasis variants (512 colours) are
+    /// accepted; only the first 64 colours are used.
+    pub fn from_pal_bytes(raw: &[u8]) -> Result<Palette, PaletteError> {
+        if raw.len() < PALETTE_SIZE * 3 {
+            return Err(PaletteError::TooShort { actual: raw.len() });
+        }
+
+        let mut colors = [(0, 0, 0); PALETTE_SIZE];
+        for (color, rgb) in colors.iter_mut().zip(raw.chunks_exact(3)) {
+            *color = (rgb[0], rgb[1], rgb[2]);
+        }
+        Ok(Palette { colors })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Palette, PaletteError> {
+        Palette::from_pal_bytes(&fs::read(path)?)
+    }
+
+    pub fn rgb(&self, index: u8) -> (u8, u8, u8) {
+        self.colors[(index & 0x3F) as usize]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            colors: SYSTEM_PALETTE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pal_file_round_trip() {
+        let raw: Vec<u8> = (0..PALETTE_SIZE * 3).map(|i| i as u8).collect();
+        let palette = Palette::from_pal_bytes(&raw).unwrap();
+        assert_eq!(palette.rgb(0x00), (0, 1, 2));
+        assert_eq!(palette.rgb(0x3F), (189, 190, 191));
+        // Only six bits select a colour.
+        assert_eq!(palette.rgb(0x40), (0, 1, 2));
+    }
+
+    #[test]
+    fn test_short_pal_file_is_rejected() {
+        assert!(matches!(
+            Palette::from_pal_bytes(&[0; 10]),
+            Err(PaletteError::TooShort { actual: 10 })
+        ));
+    }
+}
@@ -0,0 +1,1315 @@
+use crate::cartridge::Mirroring;
+use crate::frame::{Frame, WIDTH};
+use crate::mapper::SharedMapper;
+
+const DOTS_PER_SCANLINE: usize = 341;
+const VISIBLE_SCANLINES: u16 = 240;
+const VBLANK_SCANLINE: u16 = 241;
+const PRE_RENDER_SCANLINE: u16 = 261;
+
+const MAX_SPRITES_PER_SCANLINE: usize = 8;
+
+bitflags! {
+    // 7  bit  0
+    // ---- ----
+    // VHP. ..PP
+    // |||   ||
+    // |||   ++- Palette (4 to 7) of sprite
+    // ||+------ Priority (0: in front of background; 1: behind background)
+    // |+------- Flip sprite horizontally
+    // +-------- Flip sprite vertically
+    pub struct SpriteAttributes: u8 {
+        const PALETTE           = 0b0000_0011;
+        const BEHIND_BACKGROUND = 0b0010_0000;
+        const FLIP_HORIZONTAL   = 0b0100_0000;
+        const FLIP_VERTICAL     = 0b1000_0000;
+    }
+}
+
+/// A sprite picked by evaluation for the scanline being drawn, with its
+/// pattern row already fetched and flipped horizontally if needed.
+#[derive(Debug, Clone, Copy)]
+struct ScanlineSprite {
+    x: u8,
+    attributes: SpriteAttributes,
+    pattern_lo: u8,
+    pattern_hi: u8,
+    is_sprite_zero: bool,
+}
+
+impl ScanlineSprite {
+    const EMPTY: ScanlineSprite = ScanlineSprite {
+        x: 0xFF,
+        attributes: SpriteAttributes::empty(),
+        pattern_lo: 0,
+        pattern_hi: 0,
+        is_sprite_zero: false,
+    };
+}
+
+bitflags! {
+    // 7  bit  0
+    // ---- ----
+    // VPHB SINN
+    // |||| ||||
+    // |||| ||++- Base nametable address
+    // |||| ||    (0 = $2000; 1 = $2400; 2 = $2800; 3 = $2C00)
+    // |||| |+--- VRAM address increment per CPU read/write of PPUDATA
+    // |||| |     (0: add 1, going across; 1: add 32, going down)
+    // |||| +---- Sprite pattern table address for 8x8 sprites
+    // ||||       (0: $0000; 1: $1000; ignored in 8x16 mode)
+    // |||+------ Background pattern table address (0: $0000; 1: $1000)
+    // ||+------- Sprite size (0: 8x8 pixels; 1: 8x16 pixels)
+    // |+-------- PPU master/slave select
+    // |          (0: read backdrop from EXT pins; 1: output color on EXT pins)
+    // +--------- Generate an NMI at the start of the
+    //            vertical blanking interval (0: off; 1: on)
+    pub struct ControlRegister: u8 {
+        const NAMETABLE1              = 0b0000_0001;
+        const NAMETABLE2              = 0b0000_0010;
+        const VRAM_ADD_INCREMENT      = 0b0000_0100;
+        const SPRITE_PATTERN_ADDR     = 0b0000_1000;
+        const BACKROUND_PATTERN_ADDR  = 0b0001_0000;
+        const SPRITE_SIZE             = 0b0010_0000;
+        const MASTER_SLAVE_SELECT     = 0b0100_0000;
+        const GENERATE_NMI            = 0b1000_0000;
+    }
+}
+
+impl ControlRegister {
+    pub fn vram_addr_increment(&self) -> u16 {
+        if self.contains(ControlRegister::VRAM_ADD_INCREMENT) {
+            32
+        } else {
+            1
+        }
+    }
+}
+
+bitflags! {
+    // 7  bit  0
+    // ---- ----
+    // BGRs bMmG
+    // |||| ||||
+    // |||| |||+- Greyscale (0: normal color, 1: produce a greyscale display)
+    // |||| ||+-- 1: Show background in leftmost 8 pixels of screen, 0: Hide
+    // |||| |+--- 1: Show sprites in leftmost 8 pixels of screen, 0: Hide
+    // |||| +---- 1: Show background
+    // |||+------ 1: Show sprites
+    // ||+------- Emphasize red
+    // |+-------- Emphasize green
+    // +--------- Emphasize blue
+    pub struct MaskRegister: u8 {
+        const GREYSCALE                = 0b0000_0001;
+        const LEFTMOST_8PXL_BACKGROUND = 0b0000_0010;
+        const LEFTMOST_8PXL_SPRITE     = 0b0000_0100;
+        const SHOW_BACKGROUND          = 0b0000_1000;
+        const SHOW_SPRITES             = 0b0001_0000;
+        const EMPHASISE_RED            = 0b0010_0000;
+        const EMPHASISE_GREEN          = 0b0100_0000;
+        const EMPHASISE_BLUE           = 0b1000_0000;
+    }
+}
+
+bitflags! {
+    // 7  bit  0
+    // ---- ----
+    // VSO. ....
+    // |||| ||||
+    // |||+-++++- Stale PPU bus contents
+    // ||+------- Sprite overflow
+    // |+-------- Sprite 0 hit
+    // +--------- Vertical blank has started
+    pub struct StatusRegister: u8 {
+        const SPRITE_OVERFLOW = 0b0010_0000;
+        const SPRITE_ZERO_HIT = 0b0100_0000;
+        const VBLANK_STARTED  = 0b1000_0000;
+    }
+}
+
+/// The 2C02 picture processing unit as seen through its eight CPU-facing
+/// registers at $2000-$2007.
+///
+/// VRAM addressing uses the internal `v`/`t`/`x`/`w` registers: PPUSCROLL and
+/// PPUADDR both write into the temporary address `t` and share the `w` write
+/// toggle, and PPUDATA accesses go through the current address `v`.
+///
+/// While rendering, `v` doubles as the scroll position:
+///
+/// ```text
+/// yyy NN YYYYY XXXXX
+/// ||| || ||||| +++++-- coarse X scroll
+/// ||| || +++++-------- coarse Y scroll
+/// ||| ++-------------- nametable select
+/// +++----------------- fine Y scroll
+/// ```
+///
+/// and `x` holds the fine X scroll.
+pub struct NesPPU {
+    mapper: SharedMapper,
+    pub palette_table: [u8; 32],
+    /// Nametable RAM. The console has 2KB; the upper half is only used by
+    /// four-screen cartridges, which supply the extra 2KB themselves.
+    pub vram: [u8; 4096],
+    pub oam_data: [u8; 256],
+    pub oam_addr: u8,
+
+    pub ctrl: ControlRegister,
+    pub mask: MaskRegister,
+    pub status: StatusRegister,
+
+    v: u16,
+    t: u16,
+    x: u8,
+    w: bool,
+    internal_data_buf: u8,
+
+    // Background fetch latches and the 16-bit shifters they feed. The high
+    // byte of each shifter is the tile being drawn, the low byte the next one.
+    bg_next_tile_id: u8,
+    bg_next_tile_attrib: u8,
+    bg_next_tile_lsb: u8,
+    bg_next_tile_msb: u8,
+    bg_shifter_pattern_lo: u16,
+    bg_shifter_pattern_hi: u16,
+    bg_shifter_attrib_lo: u16,
+    bg_shifter_attrib_hi: u16,
+
+    /// Sprites for the scanline being drawn, in OAM order.
+    scanline_sprites: [ScanlineSprite; MAX_SPRITES_PER_SCANLINE],
+    scanline_sprite_count: usize,
+
+    /// Last value driven onto the CPU-facing data bus. Reads of write-only
+    /// registers, and the low bits of PPUSTATUS, return it.
+    io_latch: u8,
+
+    pub scanline: u16,
+    pub dot: usize,
+    /// Number of frames completed so far.
+    pub frame_count: u64,
+    /// Dots run since power-on, so the cartridge can time PPU bus activity.
+    cycles: u64,
+    pub frame: Frame,
+}
+
+impl NesPPU {
+    /// Creates a PPU wired to the cartridge, which supplies the pattern
+    /// tables and decides how the nametables are mirrored.
+    pub fn new(mapper: SharedMapper) -> Self {
+        NesPPU {
+            mapper,
+            palette_table: [0; 32],
+            vram: [0; 4096],
+            oam_data: [0; 256],
+            oam_addr: 0,
+            ctrl: ControlRegister::empty(),
+            mask: MaskRegister::empty(),
+            status: StatusRegister::empty(),
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            internal_data_buf: 0,
+            bg_next_tile_id: 0,
+            bg_next_tile_attrib: 0,
+            bg_next_tile_lsb: 0,
+            bg_next_tile_msb: 0,
+            bg_shifter_pattern_lo: 0,
+            bg_shifter_pattern_hi: 0,
+            bg_shifter_attrib_lo: 0,
+            bg_shifter_attrib_hi: 0,
+            scanline_sprites: [ScanlineSprite::EMPTY; MAX_SPRITES_PER_SCANLINE],
+            scanline_sprite_count: 0,
+            io_latch: 0,
+            scanline: 0,
+            dot: 0,
+            frame_count: 0,
+            cycles: 0,
+            frame: Frame::new(),
+        }
+    }
+
+    /// Level of the PPU's /NMI output: asserted while in vblank with NMI
+    /// generation enabled. The CPU latches NMI on the rising edge.
+    pub fn nmi_line(&self) -> bool {
+        self.status.contains(StatusRegister::VBLANK_STARTED)
+            && self.ctrl.contains(ControlRegister::GENERATE_NMI)
+    }
+
+    /// Reads register `$2000 + (addr & 7)` from the CPU side.
+    pub fn read_register(&mut self, addr: u16) -> u8 {
+        match addr & 0x0007 {
+            2 => self.read_status(),
+            4 => self.read_oam_data(),
+            7 => self.read_data(),
+            // PPUCTRL, PPUMASK, OAMADDR, PPUSCROLL and PPUADDR are write-only.
+            _ => self.io_latch,
+        }
+    }
+
+    /// Writes register `$2000 + (addr & 7)` from the CPU side.
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        self.io_latch = data;
+        match addr & 0x0007 {
+            0 => self.write_to_ctrl(data),
+            1 => self.mask = MaskRegister::from_bits_truncate(data),
+            // PPUSTATUS is read-only.
+            2 => {}
+            3 => self.oam_addr = data,
+            4 => self.write_to_oam_data(data),
+            5 => self.write_to_scroll(data),
+            6 => self.write_to_ppu_addr(data),
+            7 => self.write_to_data(data),
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_to_ctrl(&mut self, data: u8) {
+        self.ctrl = ControlRegister::from_bits_truncate(data);
+        self.t = (self.t & !0x0C00) | (((data & 0b11) as u16) << 10);
+    }
+
+    fn read_status(&mut self) -> u8 {
+        let data = self.status.bits() | (self.io_latch & 0b0001_1111);
+        self.status.remove(StatusRegister::VBLANK_STARTED);
+        self.w = false;
+        self.io_latch = data;
+        data
+    }
+
+    fn read_oam_data(&mut self) -> u8 {
+        let mut data = self.oam_data[self.oam_addr as usize];
+        // Bits 2-4 of the sprite attribute byte are not implemented.
+        if self.oam_addr & 0b11 == 2 {
+            data &= 0b1110_0011;
+        }
+        self.io_latch = data;
+        data
+    }
+
+    fn write_to_oam_data(&mut self, data: u8) {
+        self.oam_data[self.oam_addr as usize] = data;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    fn write_to_scroll(&mut self, data: u8) {
+        if !self.w {
+            self.t = (self.t & !0x001F) | (data >> 3) as u16;
+            self.x = data & 0b111;
+        } else {
+            self.t = (self.t & !0x73E0) | (((data & 0b111) as u16) << 12) | (((data >> 3) as u16) << 5);
+        }
+        self.w = !self.w;
+    }
+
+    fn write_to_ppu_addr(&mut self, data: u8) {
+        if !self.w {
+            self.t = (self.t & 0x00FF) | (((data & 0x3F) as u16) << 8);
+        } else {
+            self.t = (self.t & 0xFF00) | data as u16;
+            self.v = self.t;
+        }
+        self.w = !self.w;
+    }
+
+    fn increment_vram_addr(&mut self) {
+        self.v = self.v.wrapping_add(self.ctrl.vram_addr_increment()) & 0x7FFF;
+    }
+
+    fn read_data(&mut self) -> u8 {
+        let addr = self.v & 0x3FFF;
+        self.increment_vram_addr();
+
+        let data = match addr {
+            0x0000..=0x3EFF => {
+                let result = self.internal_data_buf;
+                self.internal_data_buf = self.read_vram(addr);
+                result
+            }
+            _ => {
+                // Palette reads bypass the buffer, which is filled with the
+                // nametable byte "underneath" the palette instead.
+                self.internal_data_buf = self.read_vram(addr - 0x1000);
+                (self.read_vram(addr) & 0b0011_1111) | (self.io_latch & 0b1100_0000)
+            }
+        };
+        self.io_latch = data;
+        data
+    }
+
+    fn write_to_data(&mut self, data: u8) {
+        let addr = self.v & 0x3FFF;
+        self.write_vram(addr, data);
+        self.increment_vram_addr();
+    }
+
+    /// Reads the PPU's own address space ($0000-$3FFF) without side effects.
+    pub fn read_vram(&self, addr: u16) -> u8 {
+        match addr & 0x3FFF {
+            addr @ 0x0000..=0x1FFF => self.mapper.borrow_mut().ppu_read(addr),
+            addr @ 0x2000..=0x3EFF => self.vram[self.mirror_vram_addr(addr)],
+            addr => self.palette_table[mirror_palette_addr(addr)],
+        }
+    }
+
+    /// Writes the PPU's own address space ($0000-$3FFF).
+    pub fn write_vram(&mut self, addr: u16, data: u8) {
+        match addr & 0x3FFF {
+            addr @ 0x0000..=0x1FFF => self.mapper.borrow_mut().ppu_write(addr, data),
+            addr @ 0x2000..=0x3EFF => {
+                let index = self.mirror_vram_addr(addr);
+                self.vram[index] = data;
+            }
+            addr => self.palette_table[mirror_palette_addr(addr)] = data & 0b0011_1111,
+        }
+    }
+
+    // Horizontal:
+    //   [ A ] [ a ]
+    //   [ B ] [ b ]
+
+    // Vertical:
+    //   [ A ] [ B ]
+    //   [ a ] [ b ]
+    fn mirror_vram_addr(&self, addr: u16) -> usize {
+        // $3000-$3EFF mirrors $2000-$2EFF.
+        let vram_index = (addr & 0x0FFF) as usize;
+        let name_table = vram_index / 0x400;
+        let offset = vram_index & 0x3FF;
+        match (self.mapper.borrow().mirroring(), name_table) {
+            (Mirroring::FourScreen, _) => vram_index,
+            (Mirroring::Vertical, _) => vram_index & 0x7FF,
+            (Mirroring::Horizontal, 0 | 1) => offset,
+            (Mirroring::Horizontal, _) => 0x400 + offset,
+            (Mirroring::SingleScreenLower, _) => offset,
+            (Mirroring::SingleScreenUpper, _) => 0x400 + offset,
+        }
+    }
+
+    /// Advances the PPU by `dots` dots (three per CPU cycle on NTSC).
+    pub fn tick(&mut self, dots: usize) {
+        for _ in 0..dots {
+            self.step_dot();
+        }
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.mask
+            .intersects(MaskRegister::SHOW_BACKGROUND | MaskRegister::SHOW_SPRITES)
+    }
+
+    fn step_dot(&mut self) {
+        self.cycles += 1;
+        self.dot += 1;
+        // With rendering on, the pre-render line is one dot shorter on odd
+        // frames.
+        let skip_dot = self.scanline == PRE_RENDER_SCANLINE
+            && self.dot == DOTS_PER_SCANLINE - 1
+            && self.frame_count % 2 == 1
+            && self.rendering_enabled();
+        if self.dot == DOTS_PER_SCANLINE || skip_dot {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline > PRE_RENDER_SCANLINE {
+                self.scanline = 0;
+                self.frame_count += 1;
+            }
+        }
+
+        let visible = self.scanline < VISIBLE_SCANLINES;
+        let pre_render = self.scanline == PRE_RENDER_SCANLINE;
+        if (visible || pre_render) && self.rendering_enabled() {
+            self.step_background_pipeline(pre_render);
+            if self.dot == 257 {
+                // OAMADDR is cleared during the sprite tile loading interval.
+                self.oam_addr = 0;
+                if visible {
+                    self.evaluate_sprites();
+                } else {
+                    self.scanline_sprite_count = 0;
+                }
+                self.fetch_empty_sprite_slots();
+            }
+        }
+
+        if visible && (1..=WIDTH).contains(&self.dot) {
+            self.render_pixel(self.dot - 1, self.scanline as usize);
+        }
+
+        if self.dot == 1 {
+            match self.scanline {
+                VBLANK_SCANLINE => self.status.insert(StatusRegister::VBLANK_STARTED),
+                PRE_RENDER_SCANLINE => self.status.remove(
+                    StatusRegister::VBLANK_STARTED
+                        | StatusRegister::SPRITE_ZERO_HIT
+                        | StatusRegister::SPRITE_OVERFLOW,
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    // Each tile takes eight dots: nametable byte, attribute byte, then the
+    // low and high pattern planes, two dots apiece. Tiles for dots 1-256 are
+    // fetched one tile ahead, and the first two tiles of the next line are
+    // fetched at dots 321-336.
+    fn step_background_pipeline(&mut self, pre_render: bool) {
+        let dot = self.dot;
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.update_shifters();
+            match (dot - 1) % 8 {
+                0 => {
+                    self.load_background_shifters();
+                    self.fetch_nametable_byte();
+                }
+                2 => self.fetch_attribute_byte(),
+                4 => self.bg_next_tile_lsb = self.fetch_pattern_byte(0),
+                6 => self.bg_next_tile_msb = self.fetch_pattern_byte(8),
+                7 => self.increment_coarse_x(),
+                _ => {}
+            }
+        }
+
+        match dot {
+            1 | 321 => self.fetch_nametable_byte(),
+            256 => self.increment_y(),
+            257 => self.copy_horizontal_bits(),
+            280..=304 if pre_render => self.copy_vertical_bits(),
+            // Unused nametable fetch at the end of the line.
+            339 => self.fetch_nametable_byte(),
+            _ => {}
+        }
+    }
+
+    fn fetch_nametable_byte(&mut self) {
+        self.bg_next_tile_id = self.read_vram(0x2000 | (self.v & 0x0FFF));
+    }
+
+    fn fetch_attribute_byte(&mut self) {
+        let addr = 0x23C0 | (self.v & 0x0C00) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07);
+        // Each attribute byte covers a 4x4 tile area split into 2x2 quadrants.
+        let shift = ((self.v >> 4) & 0b100) | (self.v & 0b10);
+        self.bg_next_tile_attrib = (self.read_vram(addr) >> shift) & 0b11;
+    }
+
+    fn fetch_pattern_byte(&self, plane: u16) -> u8 {
+        let bank = if self.ctrl.contains(ControlRegister::BACKROUND_PATTERN_ADDR) {
+            0x1000
+        } else {
+            0
+        };
+        let fine_y = (self.v >> 12) & 0b111;
+        self.fetch_pattern(bank + (self.bg_next_tile_id as u16) * 16 + fine_y + plane)
+    }
+
+    /// A rendering fetch from the pattern tables, which the cartridge gets
+    /// to watch.
+    fn fetch_pattern(&self, addr: u16) -> u8 {
+        self.mapper.borrow_mut().ppu_fetch(addr, self.cycles)
+    }
+
+    fn load_background_shifters(&mut self) {
+        self.bg_shifter_pattern_lo = (self.bg_shifter_pattern_lo & 0xFF00) | self.bg_next_tile_lsb as u16;
+        self.bg_shifter_pattern_hi = (self.bg_shifter_pattern_hi & 0xFF00) | self.bg_next_tile_msb as u16;
+
+        let spread = |bit: u8| if self.bg_next_tile_attrib & bit != 0 { 0xFF } else { 0x00 };
+        self.bg_shifter_attrib_lo = (self.bg_shifter_attrib_lo & 0xFF00) | spread(0b01);
+        self.bg_shifter_attrib_hi = (self.bg_shifter_attrib_hi & 0xFF00) | spread(0b10);
+    }
+
+    fn update_shifters(&mut self) {
+        if self.mask.contains(MaskRegister::SHOW_BACKGROUND) {
+            self.bg_shifter_pattern_lo <<= 1;
+            self.bg_shifter_pattern_hi <<= 1;
+            self.bg_shifter_attrib_lo <<= 1;
+            self.bg_shifter_attrib_hi <<= 1;
+        }
+    }
+
+    fn increment_coarse_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            self.v &= !0x001F;
+            // Wrap into the horizontally adjacent nametable.
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03E0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            // Row 29 is the last row of tiles; wrap into the vertically
+            // adjacent nametable.
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            // Rows 30 and 31 hold attributes; scrolling into them wraps
+            // without switching nametables.
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
+    }
+
+    fn copy_horizontal_bits(&mut self) {
+        self.v = (self.v & !0x041F) | (self.t & 0x041F);
+    }
+
+    fn copy_vertical_bits(&mut self) {
+        self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+    }
+
+    /// The background pixel under the shifters as `(pixel, palette)`, where
+    /// pixel 0 is transparent.
+    fn background_pixel(&self, x: usize) -> (u8, u8) {
+        let show_left = self.mask.contains(MaskRegister::LEFTMOST_8PXL_BACKGROUND);
+        if !self.mask.contains(MaskRegister::SHOW_BACKGROUND) || (x < 8 && !show_left) {
+            return (0, 0);
+        }
+
+        let mux = 0x8000 >> self.x;
+        let bit = |shifter: u16| (shifter & mux != 0) as u8;
+        let pixel = (bit(self.bg_shifter_pattern_hi) << 1) | bit(self.bg_shifter_pattern_lo);
+        let palette = (bit(self.bg_shifter_attrib_hi) << 1) | bit(self.bg_shifter_attrib_lo);
+        (pixel, palette)
+    }
+
+    fn sprite_height(&self) -> u16 {
+        if self.ctrl.contains(ControlRegister::SPRITE_SIZE) {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Picks the first eight sprites in OAM that cover the next scanline
+    /// and fetches their pattern rows.
+    ///
+    /// Hardware does this across dots 65-320; doing it all at dot 257 gives
+    /// the same result because nothing observes the intermediate state.
+    fn evaluate_sprites(&mut self) {
+        let height = self.sprite_height();
+        let in_range = |y: u8| {
+            let row = self.scanline.wrapping_sub(y as u16);
+            row < height
+        };
+
+        self.scanline_sprite_count = 0;
+        let mut n = 0;
+        while n < 64 && self.scanline_sprite_count < MAX_SPRITES_PER_SCANLINE {
+            if in_range(self.oam_data[n * 4]) {
+                let sprite = self.fetch_sprite(n);
+                self.scanline_sprites[self.scanline_sprite_count] = sprite;
+                self.scanline_sprite_count += 1;
+            }
+            n += 1;
+        }
+
+        // Once eight sprites are found the hardware keeps scanning for an
+        // overflow but, by mistake, also steps through the bytes of each
+        // entry, so it compares tile numbers, attributes and X positions as
+        // if they were Y coordinates.
+        let mut m = 0;
+        while n < 64 {
+            if in_range(self.oam_data[n * 4 + m]) {
+                self.status.insert(StatusRegister::SPRITE_OVERFLOW);
+                break;
+            }
+            n += 1;
+            m = (m + 1) & 0b11;
+        }
+    }
+
+    fn fetch_sprite(&self, n: usize) -> ScanlineSprite {
+        let y = self.oam_data[n * 4];
+        let tile = self.oam_data[n * 4 + 1];
+        let attributes = SpriteAttributes::from_bits_truncate(self.oam_data[n * 4 + 2]);
+        let x = self.oam_data[n * 4 + 3];
+
+        let height = self.sprite_height();
+        let mut row = self.scanline - y as u16;
+        if attributes.contains(SpriteAttributes::FLIP_VERTICAL) {
+            row = height - 1 - row;
+        }
+
+        let tile_addr = if height == 16 {
+            // 8x16 sprites pick their bank with bit 0 of the tile number and
+            // use the following tile for their bottom half.
+            let bank = (tile as u16 & 1) * 0x1000;
+            let tile = (tile & 0xFE) as u16 + row / 8;
+            bank + tile * 16 + row % 8
+        } else {
+            let bank = if self.ctrl.contains(ControlRegister::SPRITE_PATTERN_ADDR) {
+                0x1000
+            } else {
+                0
+            };
+            bank + tile as u16 * 16 + row
+        };
+
+        let mut pattern_lo = self.fetch_pattern(tile_addr);
+        let mut pattern_hi = self.fetch_pattern(tile_addr + 8);
+        if attributes.contains(SpriteAttributes::FLIP_HORIZONTAL) {
+            pattern_lo = pattern_lo.reverse_bits();
+            pattern_hi = pattern_hi.reverse_bits();
+        }
+
+        ScanlineSprite {
+            x,
+            attributes,
+            pattern_lo,
+            pattern_hi,
+            is_sprite_zero: n == 0,
+        }
+    }
+
+    /// Sprite slots that evaluation left empty still fetch tile $FF, so
+    /// every rendered line touches the sprite pattern table. The MMC3's
+    /// scanline counter relies on it.
+    fn fetch_empty_sprite_slots(&self) {
+        let tile_addr = if self.sprite_height() == 16 {
+            0x1000 + 0xFE * 16
+        } else if self.ctrl.contains(ControlRegister::SPRITE_PATTERN_ADDR) {
+            0x1000 + 0xFF * 16
+        } else {
+            0xFF * 16
+        };
+        for _ in self.scanline_sprite_count..MAX_SPRITES_PER_SCANLINE {
+            self.fetch_pattern(tile_addr);
+            self.fetch_pattern(tile_addr + 8);
+        }
+    }
+
+    /// The frontmost opaque sprite pixel at `x`, if any, as the sprite and
+    /// its 2-bit pixel value.
+    fn sprite_pixel(&self, x: usize) -> Option<(ScanlineSprite, u8)> {
+        let show_left = self.mask.contains(MaskRegister::LEFTMOST_8PXL_SPRITE);
+        if !self.mask.contains(MaskRegister::SHOW_SPRITES) || (x < 8 && !show_left) {
+            return None;
+        }
+
+        self.scanline_sprites[..self.scanline_sprite_count]
+            .iter()
+            .find_map(|sprite| {
+                let column = x.checked_sub(sprite.x as usize).filter(|&c| c < 8)?;
+                let bit = |plane: u8| (plane >> (7 - column)) & 1;
+                let pixel = (bit(sprite.pattern_hi) << 1) | bit(sprite.pattern_lo);
+                (pixel != 0).then_some((*sprite, pixel))
+            })
+    }
+
+    fn render_pixel(&mut self, x: usize, y: usize) {
+        let palette_addr = if !self.rendering_enabled() && self.v & 0x3F00 == 0x3F00 {
+            // With rendering off, the backdrop comes from wherever `v`
+            // points inside palette RAM.
+            self.v
+        } else {
+            let (bg_pixel, bg_palette) = self.background_pixel(x);
+            let sprite = self.sprite_pixel(x);
+
+            if let Some((sprite, _)) = sprite {
+                // Sprite 0 hit never triggers at x=255.
+                if sprite.is_sprite_zero && bg_pixel != 0 && x != 255 {
+                    self.status.insert(StatusRegister::SPRITE_ZERO_HIT);
+                }
+            }
+
+            match sprite {
+                Some((sprite, pixel))
+                    if bg_pixel == 0
+                        || !sprite.attributes.contains(SpriteAttributes::BEHIND_BACKGROUND) =>
+                {
+                    let palette = (sprite.attributes & SpriteAttributes::PALETTE).bits();
+                    0x3F10 + (palette as u16) * 4 + pixel as u16
+                }
+                _ if bg_pixel == 0 => 0x3F00,
+                _ => 0x3F00 + (bg_palette as u16) * 4 + bg_pixel as u16,
+            }
+        };
+
+        let mut index = self.read_vram(palette_addr) & 0x3F;
+        if self.mask.contains(MaskRegister::GREYSCALE) {
+            index &= 0x30;
+        }
+        self.frame.set_pixel(x, y, index);
+    }
+}
+
+/// $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C, and the
+/// whole 32-byte table repeats up to $3FFF.
+fn mirror_palette_addr(addr: u16) -> usize {
+    let index = (addr & 0x1F) as usize;
+    if index >= 0x10 && index & 0b11 == 0 {
+        index - 0x10
+    } else {
+        index
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::mapper;
+    use std::ops::Range;
+
+    /// A PPU on an NROM board with the given CHR and nametable mirroring.
+    /// Empty `chr` means CHR-RAM.
+    fn new_ppu(chr: Vec<u8>, mirroring: Mirroring) -> NesPPU {
+        let mut rom = test_rom(&[]);
+        rom.chr_rom = chr;
+        rom.info.screen_mirroring = mirroring;
+        NesPPU::new(mapper::from_rom(rom).unwrap())
+    }
+
+    fn new_empty_rom_ppu() -> NesPPU {
+        new_ppu(vec![0; 2048], Mirroring::Horizontal)
+    }
+
+    fn fill_chr(ppu: &mut NesPPU, range: Range<u16>, data: u8) {
+        for addr in range {
+            ppu.write_vram(addr, data);
+        }
+    }
+
+    fn set_ppu_addr(ppu: &mut NesPPU, addr: u16) {
+        ppu.write_register(0x2006, (addr >> 8) as u8);
+        ppu.write_register(0x2006, (addr & 0xff) as u8);
+    }
+
+    #[test]
+    fn test_ppu_vram_writes() {
+        let mut ppu = new_empty_rom_ppu();
+        set_ppu_addr(&mut ppu, 0x2305);
+        ppu.write_register(0x2007, 0x66);
+
+        assert_eq!(ppu.vram[0x0305], 0x66);
+    }
+
+    #[test]
+    fn test_ppu_vram_reads() {
+        let mut ppu = new_empty_rom_ppu();
+        ppu.write_register(0x2000, 0);
+        ppu.vram[0x0305] = 0x66;
+
+        set_ppu_addr(&mut ppu, 0x2305);
+        ppu.read_register(0x2007); // load into the buffer
+        assert_eq!(ppu.v, 0x2306);
+        assert_eq!(ppu.read_register(0x2007), 0x66);
+    }
+
+    #[test]
+    fn test_ppu_vram_reads_cross_page() {
+        let mut ppu = new_empty_rom_ppu();
+        ppu.vram[0x01ff] = 0x66;
+        ppu.vram[0x0200] = 0x77;
+
+        set_ppu_addr(&mut ppu, 0x21ff);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x66);
+        assert_eq!(ppu.read_register(0x2007), 0x77);
+    }
+
+    #[test]
+    fn test_ppu_vram_reads_step_32() {
+        let mut ppu = new_empty_rom_ppu();
+        ppu.write_register(0x2000, 0b100);
+        ppu.vram[0x01ff] = 0x66;
+        ppu.vram[0x01ff + 32] = 0x77;
+        ppu.vram[0x01ff + 64] = 0x88;
+
+        set_ppu_addr(&mut ppu, 0x21ff);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x66);
+        assert_eq!(ppu.read_register(0x2007), 0x77);
+        assert_eq!(ppu.read_register(0x2007), 0x88);
+    }
+
+    // Horizontal: https://wiki.nesdev.com/w/index.php/Mirroring
+    //   [0x2000 A ] [0x2400 a ]
+    //   [0x2800 B ] [0x2C00 b ]
+    #[test]
+    fn test_vram_horizontal_mirror() {
+        let mut ppu = new_empty_rom_ppu();
+        set_ppu_addr(&mut ppu, 0x2405);
+        ppu.write_register(0x2007, 0x66); // write to a
+
+        set_ppu_addr(&mut ppu, 0x2805);
+        ppu.write_register(0x2007, 0x77); // write to B
+
+        set_ppu_addr(&mut ppu, 0x2005);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x66); // read from A
+
+        set_ppu_addr(&mut ppu, 0x2C05);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x77); // read from b
+    }
+
+    // Vertical: https://wiki.nesdev.com/w/index.php/Mirroring
+    //   [0x2000 A ] [0x2400 B ]
+    //   [0x2800 a ] [0x2C00 b ]
+    #[test]
+    fn test_vram_vertical_mirror() {
+        let mut ppu = new_ppu(vec![0; 2048], Mirroring::Vertical);
+
+        set_ppu_addr(&mut ppu, 0x2005);
+        ppu.write_register(0x2007, 0x66); // write to A
+
+        set_ppu_addr(&mut ppu, 0x2C05);
+        ppu.write_register(0x2007, 0x77); // write to b
+
+        set_ppu_addr(&mut ppu, 0x2805);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x66); // read from a
+
+        set_ppu_addr(&mut ppu, 0x2405);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x77); // read from B
+    }
+
+    #[test]
+    fn test_vram_single_screen_and_four_screen_mirror() {
+        let mut ppu = new_ppu(vec![0; 2048], Mirroring::SingleScreenUpper);
+        ppu.write_vram(0x2C05, 0x66);
+        assert_eq!(ppu.vram[0x0405], 0x66);
+        assert_eq!(ppu.read_vram(0x2005), 0x66);
+
+        let mut ppu = new_ppu(vec![0; 2048], Mirroring::FourScreen);
+        ppu.write_vram(0x2C05, 0x77);
+        assert_eq!(ppu.vram[0x0C05], 0x77);
+        assert_eq!(ppu.read_vram(0x2005), 0x00);
+    }
+
+    #[test]
+    fn test_vram_mirroring_above_3000() {
+        let mut ppu = new_empty_rom_ppu();
+        ppu.write_vram(0x3105, 0x66);
+        assert_eq!(ppu.read_vram(0x2105), 0x66);
+    }
+
+    #[test]
+    fn test_read_status_resets_latch() {
+        let mut ppu = new_empty_rom_ppu();
+        ppu.vram[0x0305] = 0x66;
+
+        ppu.write_register(0x2006, 0x21);
+        ppu.write_register(0x2006, 0x23);
+        ppu.write_register(0x2006, 0x05);
+
+        ppu.read_register(0x2007);
+        assert_ne!(ppu.read_register(0x2007), 0x66);
+
+        ppu.read_register(0x2002);
+
+        set_ppu_addr(&mut ppu, 0x2305);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x66);
+    }
+
+    #[test]
+    fn test_read_status_resets_vblank() {
+        let mut ppu = new_empty_rom_ppu();
+        ppu.status.insert(StatusRegister::VBLANK_STARTED);
+
+        let status = ppu.read_register(0x2002);
+
+        assert_eq!(status >> 7, 1);
+        assert_eq!(ppu.read_register(0x2002) >> 7, 0);
+    }
+
+    #[test]
+    fn test_write_only_registers_read_back_io_latch() {
+        let mut ppu = new_empty_rom_ppu();
+        ppu.write_register(0x2003, 0x5A);
+        assert_eq!(ppu.read_register(0x2000), 0x5A);
+        assert_eq!(ppu.read_register(0x2002) & 0b0001_1111, 0x1A);
+    }
+
+    #[test]
+    fn test_scroll_and_addr_share_write_latch() {
+        let mut ppu = new_empty_rom_ppu();
+        ppu.write_register(0x2005, 0b0111_1101); // coarse X 15, fine X 5
+        ppu.write_register(0x2006, 0x23);
+        ppu.write_register(0x2006, 0x05);
+
+        // PPUADDR's first write finished the pair started by PPUSCROLL and
+        // landed in the low byte; the second one began a new pair.
+        assert_eq!(ppu.x, 0b101);
+        assert_eq!(ppu.v, 0x0023);
+        assert_eq!(ppu.t, 0x0523);
+        assert!(ppu.w);
+    }
+
+    #[test]
+    fn test_oam_read_write() {
+        let mut ppu = new_empty_rom_ppu();
+        ppu.write_register(0x2003, 0x10);
+        ppu.write_register(0x2004, 0x66);
+        ppu.write_register(0x2004, 0x77);
+
+        ppu.write_register(0x2003, 0x10);
+        assert_eq!(ppu.read_register(0x2004), 0x66);
+
+        ppu.write_register(0x2003, 0x11);
+        assert_eq!(ppu.read_register(0x2004), 0x77);
+    }
+
+    #[test]
+    fn test_oam_attribute_unimplemented_bits_read_as_zero() {
+        let mut ppu = new_empty_rom_ppu();
+        ppu.oam_data[6] = 0xFF;
+        ppu.write_register(0x2003, 6);
+        assert_eq!(ppu.read_register(0x2004), 0xE3);
+    }
+
+    #[test]
+    fn test_palette_reads_are_unbuffered_and_mirrored() {
+        let mut ppu = new_empty_rom_ppu();
+        ppu.vram[0x0F00] = 0x99;
+        ppu.write_vram(0x3F10, 0x21);
+
+        assert_eq!(ppu.palette_table[0], 0x21);
+
+        set_ppu_addr(&mut ppu, 0x3F00);
+        assert_eq!(ppu.read_register(0x2007), 0x21);
+        // The buffer picked up the nametable byte under the palette.
+        assert_eq!(ppu.internal_data_buf, ppu.read_vram(0x2F00));
+
+        assert_eq!(ppu.read_vram(0x3F20), 0x21);
+    }
+
+    #[test]
+    fn test_chr_rom_ignores_writes_and_chr_ram_keeps_them() {
+        let mut ppu = new_ppu(vec![2; 0x2000], Mirroring::Horizontal);
+        ppu.write_vram(0x0010, 0x66);
+        assert_eq!(ppu.read_vram(0x0010), 2);
+
+        let mut ppu = new_ppu(vec![], Mirroring::Horizontal);
+        ppu.write_vram(0x1FFF, 0x66);
+        assert_eq!(ppu.read_vram(0x1FFF), 0x66);
+    }
+
+    #[test]
+    fn test_vblank_raises_nmi_line_when_enabled() {
+        let mut ppu = new_empty_rom_ppu();
+        ppu.tick(VBLANK_SCANLINE as usize * DOTS_PER_SCANLINE);
+        assert!(!ppu.status.contains(StatusRegister::VBLANK_STARTED));
+
+        ppu.tick(1);
+        assert!(ppu.status.contains(StatusRegister::VBLANK_STARTED));
+        assert!(!ppu.nmi_line());
+
+        ppu.write_register(0x2000, 0b1000_0000);
+        assert!(ppu.nmi_line());
+
+        ppu.tick((PRE_RENDER_SCANLINE - VBLANK_SCANLINE) as usize * DOTS_PER_SCANLINE);
+        assert!(!ppu.status.contains(StatusRegister::VBLANK_STARTED));
+        assert!(!ppu.nmi_line());
+    }
+
+    const DOTS_PER_FRAME: usize = DOTS_PER_SCANLINE * (PRE_RENDER_SCANLINE as usize + 1);
+
+    /// A PPU whose tile 1 is solid colour 1 and tile 2 is solid colour 3,
+    /// with background palette 0 = $0F/$21/../$2C and palette 1 = ../$16.
+    fn new_tile_ppu() -> NesPPU {
+        let mut ppu = new_ppu(vec![], Mirroring::Horizontal);
+        fill_chr(&mut ppu, 0x10..0x18, 0xFF);
+        fill_chr(&mut ppu, 0x20..0x30, 0xFF);
+        ppu.write_vram(0x3F00, 0x0F);
+        ppu.write_vram(0x3F01, 0x21);
+        ppu.write_vram(0x3F03, 0x2C);
+        ppu.write_vram(0x3F07, 0x16);
+        ppu.write_register(0x2001, 0b0000_1010);
+        ppu
+    }
+
+    #[test]
+    fn test_background_tiles_render_into_frame() {
+        let mut ppu = new_tile_ppu();
+        ppu.vram[0] = 1;
+        ppu.vram[33] = 2;
+        // Top-right quadrant of the first attribute byte uses palette 1.
+        ppu.vram[0x3C0] = 0b0000_0100;
+
+        ppu.tick(DOTS_PER_FRAME * 2);
+
+        assert_eq!(ppu.frame.pixel(0, 0), 0x21);
+        assert_eq!(ppu.frame.pixel(7, 7), 0x21);
+        assert_eq!(ppu.frame.pixel(8, 0), 0x0F);
+        assert_eq!(ppu.frame.pixel(0, 8), 0x0F);
+        // Tile 2 at column 1, row 1 sits in palette 0's quadrant.
+        assert_eq!(ppu.frame.pixel(8, 8), 0x2C);
+        assert_eq!(ppu.frame.pixel(15, 15), 0x2C);
+
+        ppu.vram[2] = 2;
+        ppu.tick(DOTS_PER_FRAME * 2);
+        assert_eq!(ppu.frame.pixel(16, 0), 0x16);
+    }
+
+    #[test]
+    fn test_fine_x_scroll_shifts_pixels() {
+        let mut ppu = new_tile_ppu();
+        ppu.vram[0] = 1;
+        ppu.write_register(0x2005, 3);
+        ppu.write_register(0x2005, 0);
+
+        ppu.tick(DOTS_PER_FRAME * 2);
+
+        assert_eq!(ppu.frame.pixel(4, 0), 0x21);
+        assert_eq!(ppu.frame.pixel(5, 0), 0x0F);
+    }
+
+    #[test]
+    fn test_coarse_scroll_wraps_into_next_nametable() {
+        let mut ppu = new_ppu(vec![0; 0x2000], Mirroring::Vertical);
+        ppu.v = 31;
+        ppu.increment_coarse_x();
+        assert_eq!(ppu.v, 0x0400);
+
+        ppu.v = 0x7000 | (29 << 5);
+        ppu.increment_y();
+        assert_eq!(ppu.v, 0x0800);
+
+        ppu.v = 0x7000 | (31 << 5);
+        ppu.increment_y();
+        assert_eq!(ppu.v, 0x0000);
+    }
+
+    #[test]
+    fn test_hidden_left_column_and_greyscale() {
+        let mut ppu = new_tile_ppu();
+        ppu.vram[0] = 1;
+        ppu.write_register(0x2001, 0b0000_1001);
+
+        ppu.tick(DOTS_PER_FRAME * 2);
+
+        assert_eq!(ppu.frame.pixel(0, 0), 0x0F & 0x30);
+    }
+
+    #[test]
+    fn test_rendering_disabled_draws_backdrop() {
+        let mut ppu = new_ppu(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.write_vram(0x3F00, 0x2A);
+        ppu.tick(DOTS_PER_FRAME);
+        assert!(ppu.frame.data.iter().all(|&index| index == 0x2A));
+    }
+
+    #[test]
+    fn test_odd_frames_skip_a_dot_when_rendering() {
+        let mut ppu = new_tile_ppu();
+        ppu.tick(DOTS_PER_FRAME * 2);
+        assert_eq!(ppu.frame_count, 2);
+        assert_eq!((ppu.scanline, ppu.dot), (0, 1));
+    }
+
+    /// `new_tile_ppu` with sprites enabled, OAM cleared off-screen, tile 3
+    /// as a single top-left pixel of colour 1, tile 4 as solid colour 2, and
+    /// sprite palettes 0 = ../$05/$06 and 1 = ../$09.
+    fn new_sprite_ppu() -> NesPPU {
+        let mut ppu = new_tile_ppu();
+        ppu.write_vram(0x30, 0b1000_0000);
+        fill_chr(&mut ppu, 0x48..0x50, 0xFF);
+        ppu.write_vram(0x3F11, 0x05);
+        ppu.write_vram(0x3F12, 0x06);
+        ppu.write_vram(0x3F15, 0x09);
+        ppu.oam_data.fill(0xFF);
+        ppu.write_register(0x2001, 0b0001_1110);
+        ppu
+    }
+
+    fn set_sprite(ppu: &mut NesPPU, n: usize, y: u8, tile: u8, attributes: u8, x: u8) {
+        ppu.oam_data[n * 4..n * 4 + 4].copy_from_slice(&[y, tile, attributes, x]);
+    }
+
+    /// Runs a couple of frames so the pipeline has settled, then stops at
+    /// the start of `scanline`.
+    fn run_to_scanline(ppu: &mut NesPPU, scanline: u16) {
+        ppu.tick(DOTS_PER_FRAME * 2);
+        while ppu.scanline != scanline || ppu.dot != 0 {
+            ppu.tick(1);
+        }
+    }
+
+    #[test]
+    fn test_sprite_renders_one_line_below_oam_y() {
+        let mut ppu = new_sprite_ppu();
+        set_sprite(&mut ppu, 0, 9, 4, 0, 20);
+
+        ppu.tick(DOTS_PER_FRAME * 2);
+
+        assert_eq!(ppu.frame.pixel(20, 10), 0x06);
+        assert_eq!(ppu.frame.pixel(27, 17), 0x06);
+        assert_eq!(ppu.frame.pixel(28, 10), 0x0F);
+        assert_eq!(ppu.frame.pixel(20, 9), 0x0F);
+        assert_eq!(ppu.frame.pixel(20, 18), 0x0F);
+    }
+
+    #[test]
+    fn test_sprite_flips() {
+        let mut ppu = new_sprite_ppu();
+        set_sprite(&mut ppu, 0, 49, 3, 0b0000_0001, 40);
+        set_sprite(&mut ppu, 1, 49, 3, 0b0100_0000, 60);
+        set_sprite(&mut ppu, 2, 49, 3, 0b1000_0000, 80);
+
+        ppu.tick(DOTS_PER_FRAME * 2);
+
+        assert_eq!(ppu.frame.pixel(40, 50), 0x09);
+        assert_eq!(ppu.frame.pixel(67, 50), 0x05);
+        assert_eq!(ppu.frame.pixel(60, 50), 0x0F);
+        assert_eq!(ppu.frame.pixel(80, 57), 0x05);
+        assert_eq!(ppu.frame.pixel(80, 50), 0x0F);
+    }
+
+    #[test]
+    fn test_sprite_priority_against_background() {
+        let mut ppu = new_sprite_ppu();
+        ppu.vram[0] = 1;
+        ppu.vram[2] = 1;
+        set_sprite(&mut ppu, 0, 0, 4, 0b0010_0000, 0);
+        set_sprite(&mut ppu, 1, 0, 4, 0b0000_0000, 16);
+        // Behind the background, but the background is transparent here.
+        set_sprite(&mut ppu, 2, 0, 4, 0b0010_0000, 32);
+
+        ppu.tick(DOTS_PER_FRAME * 2);
+
+        assert_eq!(ppu.frame.pixel(0, 1), 0x21);
+        assert_eq!(ppu.frame.pixel(16, 1), 0x06);
+        assert_eq!(ppu.frame.pixel(32, 1), 0x06);
+    }
+
+    #[test]
+    fn test_front_sprite_wins_over_later_sprites() {
+        let mut ppu = new_sprite_ppu();
+        set_sprite(&mut ppu, 0, 0, 4, 0b0000_0001, 0);
+        set_sprite(&mut ppu, 1, 0, 4, 0b0000_0000, 4);
+
+        ppu.tick(DOTS_PER_FRAME * 2);
+
+        // Sprite 0's palette 1 colour 2 is $3F16, which is unset.
+        assert_eq!(ppu.frame.pixel(4, 1), 0x00);
+        assert_eq!(ppu.frame.pixel(8, 1), 0x06);
+    }
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        let mut ppu = new_sprite_ppu();
+        ppu.vram[0] = 1;
+        set_sprite(&mut ppu, 0, 0, 4, 0, 4);
+
+        run_to_scanline(&mut ppu, 1);
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+        run_to_scanline(&mut ppu, 2);
+        assert!(ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+
+        // Cleared on the pre-render line.
+        run_to_scanline(&mut ppu, 0);
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_needs_opaque_background() {
+        let mut ppu = new_sprite_ppu();
+        ppu.vram[0] = 1;
+        set_sprite(&mut ppu, 0, 0, 4, 0, 8);
+        set_sprite(&mut ppu, 1, 0, 4, 0, 0);
+
+        run_to_scanline(&mut ppu, 10);
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_respects_left_column_clipping() {
+        let mut ppu = new_sprite_ppu();
+        ppu.vram[0] = 1;
+        set_sprite(&mut ppu, 0, 0, 4, 0, 0);
+        ppu.write_register(0x2001, 0b0001_1010);
+
+        run_to_scanline(&mut ppu, 10);
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+    }
+
+    #[test]
+    fn test_only_eight_sprites_per_scanline_and_overflow() {
+        let mut ppu = new_sprite_ppu();
+        for n in 0..8 {
+            set_sprite(&mut ppu, n, 99, 4, 0, n as u8 * 10);
+        }
+
+        run_to_scanline(&mut ppu, 110);
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+
+        set_sprite(&mut ppu, 8, 99, 4, 0, 80);
+        run_to_scanline(&mut ppu, 110);
+        assert!(ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+        assert_eq!(ppu.frame.pixel(70, 100), 0x06);
+        assert_eq!(ppu.frame.pixel(80, 100), 0x0F);
+    }
+
+    #[test]
+    fn test_sprite_overflow_hardware_bug() {
+        let mut ppu = new_sprite_ppu();
+        for n in 0..8 {
+            set_sprite(&mut ppu, n, 99, 4, 0, n as u8 * 10);
+        }
+        // Sprite 9 is nowhere near line 100, but after sprite 8 misses the
+        // evaluator reads sprite 9's tile number as its Y coordinate.
+        set_sprite(&mut ppu, 9, 0xF0, 99, 0, 0);
+
+        run_to_scanline(&mut ppu, 110);
+        assert!(ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+    }
+
+    #[test]
+    fn test_sprite_fetches_clock_mmc3_once_per_scanline() {
+        let mut rom = test_rom(&[]);
+        rom.info.mapper = 4;
+        let mut ppu = NesPPU::new(mapper::from_rom(rom).unwrap());
+        {
+            let mut mapper = ppu.mapper.borrow_mut();
+            mapper.cpu_write(0xC000, 9);
+            mapper.cpu_write(0xC001, 0);
+            mapper.cpu_write(0xE001, 0);
+        }
+        // Background at $0000 and sprites at $1000, with no sprites on screen.
+        ppu.write_register(0x2000, 0b0000_1000);
+        ppu.write_register(0x2001, 0b0001_1000);
+        ppu.oam_data.fill(0xFF);
+
+        // Line 0 reloads the counter with 9; line 9 takes it to 0.
+        while (ppu.scanline, ppu.dot) != (9, 256) {
+            ppu.tick(1);
+        }
+        assert!(!ppu.mapper.borrow().irq_line());
+        ppu.tick(1);
+        assert!(ppu.mapper.borrow().irq_line());
+    }
+
+    #[test]
+    fn test_8x16_sprites() {
+        let mut ppu = new_sprite_ppu();
+        // Tiles $104 and $105: top half colour 1, bottom half colour 2.
+        fill_chr(&mut ppu, 0x1040..0x1048, 0xFF);
+        fill_chr(&mut ppu, 0x1058..0x1060, 0xFF);
+        ppu.write_register(0x2000, 0b0010_0000);
+        set_sprite(&mut ppu, 0, 29, 0x05, 0, 0);
+        set_sprite(&mut ppu, 1, 29, 0x05, 0b1000_0000, 8);
+
+        ppu.tick(DOTS_PER_FRAME * 2);
+
+        assert_eq!(ppu.frame.pixel(0, 30), 0x05);
+        assert_eq!(ppu.frame.pixel(0, 45), 0x06);
+        assert_eq!(ppu.frame.pixel(0, 46), 0x0F);
+        // Flipping vertically swaps the halves.
+        assert_eq!(ppu.frame.pixel(8, 30), 0x06);
+        assert_eq!(ppu.frame.pixel(8, 45), 0x05);
+    }
+}
@@ -0,0 +1,173 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::mapper::SharedMapper;
+
+/// How often `end_frame` writes changed RAM back to disk: about five
+/// seconds of NTSC frames.
+pub const FLUSH_INTERVAL_FRAMES: u32 = 300;
+
+/// The `.sav` file that sits next to `rom_path`.
+pub fn save_path_for<P: AsRef<Path>>(rom_path: P) -> PathBuf {
+    rom_path.as_ref().with_extension("sav")
+}
+
+/// Keeps a cartridge's battery-backed PRG-RAM in a `.sav` file.
+///
+/// The file is a raw dump of PRG-RAM, the layout other emulators use.
+/// Writes go to a temporary file that is synced and then renamed over the
+/// old save, so a crash leaves either the old save or the new one. RAM is
+/// written back every `FLUSH_INTERVAL_FRAMES` frames if it changed, and
+/// once more when the `BatterySave` is dropped.
+pub struct BatterySave {
+    path: PathBuf,
+    mapper: SharedMapper,
+    /// PRG-RAM as of the last load or flush.
+    saved: Vec<u8>,
+    frames_since_flush: u32,
+}
+
+impl BatterySave {
+    /// Attaches `path` to the cartridge, loading it into PRG-RAM if it
+    /// exists. A missing file just means the game has never been saved.
+    pub fn open<P: AsRef<Path>>(path: P, mapper: SharedMapper) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        match fs::read(&path) {
+            Ok(data) => {
+                let mut mapper = mapper.borrow_mut();
+                let prg_ram = &mut mapper.memory_mut().prg_ram;
+                let len = data.len().min(prg_ram.len());
+                prg_ram[..len].copy_from_slice(&data[..len]);
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        let saved = mapper.borrow().memory().prg_ram.clone();
+        Ok(BatterySave {
+            path,
+            mapper,
+            saved,
+            frames_since_flush: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Call once per emulated frame. Returns whether the save was written.
+    pub fn end_frame(&mut self) -> io::Result<bool> {
+        self.frames_since_flush += 1;
+        if self.frames_since_flush < FLUSH_INTERVAL_FRAMES {
+            return Ok(false);
+        }
+        self.flush()
+    }
+
+    /// Writes PRG-RAM to disk if it changed since the last write. Returns
+    /// whether the save was written.
+    pub fn flush(&mut self) -> io::Result<bool> {
+        self.frames_since_flush = 0;
+        let prg_ram = self.mapper.borrow().memory().prg_ram.clone();
+        if prg_ram == self.saved {
+            return Ok(false);
+        }
+
+        let tmp_path = self.path.with_extension("sav.tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&prg_ram)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.saved = prg_ram;
+        Ok(true)
+    }
+}
+
+impl Drop for BatterySave {
+    fn drop(&mut self) {
+        // Nobody is left to report the error to on shutdown.
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::{self, test::banked_rom};
+
+    /// A fresh directory under the system temp dir for one test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nes-save-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn new_mapper() -> SharedMapper {
+        mapper::from_rom(banked_rom(1, 2, 0)).unwrap()
+    }
+
+    #[test]
+    fn test_save_path_sits_next_to_rom() {
+        assert_eq!(save_path_for("roms/zelda.nes"), Path::new("roms/zelda.sav"));
+    }
+
+    #[test]
+    fn test_open_loads_existing_save() {
+        let path = temp_dir("load").join("game.sav");
+        fs::write(&path, [0x12, 0x34]).unwrap();
+
+        let mapper = new_mapper();
+        let _save = BatterySave::open(&path, mapper.clone()).unwrap();
+        assert_eq!(mapper.borrow_mut().cpu_read(0x6000), Some(0x12));
+        assert_eq!(mapper.borrow_mut().cpu_read(0x6001), Some(0x34));
+    }
+
+    #[test]
+    fn test_flush_writes_only_changes() {
+        let dir = temp_dir("flush");
+        let path = dir.join("game.sav");
+        let mapper = new_mapper();
+        let mut save = BatterySave::open(&path, mapper.clone()).unwrap();
+
+        assert!(!save.flush().unwrap());
+        assert!(!path.exists());
+
+        mapper.borrow_mut().cpu_write(0x7FFF, 0x99);
+        assert!(save.flush().unwrap());
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len(), 0x2000);
+        assert_eq!(data[0x1FFF], 0x99);
+        // The temporary file was renamed into place.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_end_frame_flushes_periodically() {
+        let path = temp_dir("periodic").join("game.sav");
+        let mapper = new_mapper();
+        let mut save = BatterySave::open(&path, mapper.clone()).unwrap();
+        mapper.borrow_mut().cpu_write(0x6000, 0x42);
+
+        for _ in 1..FLUSH_INTERVAL_FRAMES {
+            assert!(!save.end_frame().unwrap());
+        }
+        assert!(save.end_frame().unwrap());
+        assert_eq!(fs::read(&path).unwrap()[0], 0x42);
+    }
+
+    #[test]
+    fn test_drop_flushes() {
+        let path = temp_dir("drop").join("game.sav");
+        let mapper = new_mapper();
+        let save = BatterySave::open(&path, mapper.clone()).unwrap();
+        mapper.borrow_mut().cpu_write(0x6000, 0x42);
+
+        drop(save);
+        assert_eq!(fs::read(&path).unwrap()[0], 0x42);
+    }
+}
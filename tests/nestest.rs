@@ -0,0 +1,214 @@
+//! Runs kevtris' nestest in its automated mode, which starts at $C000 and
+//! needs nothing but the CPU. The ROM writes an error code for the official
+//! opcode tests to $02 and for the unofficial ones to $03; zero means pass.
+//!
+//! `nestest_matches_log` also steps through the reference `nestest.log` and
+//! compares PC, registers and cycle count before every instruction, which
+//! pins down the first instruction that goes wrong.
+//!
+//! Neither file is redistributed here. Drop `nestest.nes` and `nestest.log`
+//! into `tests/roms/` and run `cargo test --test nestest -- --ignored`; CI
+//! fetches both. A small hand-assembled image in the same style runs by
+//! default so the harness itself is covered.
+
+use std::fs;
+
+use nes_emulator::bus::NesBus;
+use nes_emulator::cartridge::{Rom, PRG_ROM_PAGE_SIZE};
+use nes_emulator::cpu::cpu::{BrkBehavior, CPU};
+
+const ROM_PATH: &str = "tests/roms/nestest.nes";
+const LOG_PATH: &str = "tests/roms/nestest.log";
+const END_OF_TESTS: u16 = 0xC66E;
+
+fn automated_mode_cpu(rom: Rom) -> CPU<NesBus> {
+    let mut cpu = CPU::with_bus(NesBus::new(rom).unwrap());
+    cpu.brk_behavior = BrkBehavior::Interrupt;
+    cpu.reset();
+    cpu.program_counter = 0xC000;
+    cpu
+}
+
+/// Runs `rom` from $C000 until the PC reaches `end` and returns the
+/// official and unofficial error codes from $02 and $03.
+fn run_automated(rom: Rom, end: u16) -> (u8, u8) {
+    let mut cpu = automated_mode_cpu(rom);
+    cpu.run_until(|cpu| cpu.program_counter == end).unwrap();
+
+    (cpu.mem_read(0x02), cpu.mem_read(0x03))
+}
+
+#[test]
+#[ignore = "requires tests/roms/nestest.nes"]
+fn nestest_automated_mode() {
+    let rom = Rom::from_file(ROM_PATH).expect("could not load tests/roms/nestest.nes");
+
+    let (official, unofficial) = run_automated(rom, END_OF_TESTS);
+    assert_eq!(official, 0x00, "official opcode tests failed");
+    assert_eq!(unofficial, 0x00, "unofficial opcode tests failed");
+}
+
+/// CPU state in a nestest.log line, e.g.
+/// `C000  4C F5 C5  JMP $C5F5      A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`.
+#[derive(Debug, PartialEq, Eq)]
+struct TraceState {
+    pc: u16,
+    opcode: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    sp: u8,
+    cycles: usize,
+}
+
+impl TraceState {
+    fn parse(line: &str) -> TraceState {
+        let hex = |text: &str| u16::from_str_radix(text, 16).unwrap();
+        let register = |name: &str| {
+            let start = line.find(name).unwrap() + name.len();
+            hex(&line[start..start + 2]) as u8
+        };
+        let cycles = line[line.find("CYC:").unwrap() + 4..].trim();
+
+        TraceState {
+            pc: hex(&line[0..4]),
+            opcode: hex(&line[6..8]) as u8,
+            a: register(" A:"),
+            x: register(" X:"),
+            y: register(" Y:"),
+            p: register(" P:"),
+            sp: register(" SP:"),
+            cycles: cycles.parse().unwrap(),
+        }
+    }
+
+    fn of(cpu: &mut CPU<NesBus>) -> TraceState {
+        TraceState {
+            pc: cpu.program_counter,
+            opcode: cpu.mem_read(cpu.program_counter),
+            a: cpu.register_a,
+            x: cpu.register_x,
+            y: cpu.register_y,
+            p: cpu.status.bits(),
+            sp: cpu.stack_pointer,
+            cycles: cpu.cycles,
+        }
+    }
+}
+
+#[test]
+#[ignore = "requires tests/roms/nestest.nes and tests/roms/nestest.log"]
+fn nestest_matches_log() {
+    let rom = Rom::from_file(ROM_PATH).expect("could not load tests/roms/nestest.nes");
+    let log = fs::read_to_string(LOG_PATH).expect("could not read tests/roms/nestest.log");
+    let mut cpu = automated_mode_cpu(rom);
+
+    for (number, line) in log.lines().enumerate() {
+        let expected = TraceState::parse(line);
+        let actual = TraceState::of(&mut cpu);
+        assert_eq!(actual, expected, "diverged before nestest.log line {}: {}", number + 1, line);
+        cpu.step().unwrap();
+    }
+}
+
+/// Appends "CMP #expected; BEQ +4; LDA #code; STA result".
+fn expect_a(program: &mut Vec<u8>, expected: u8, result: u8, code: u8) {
+    program.extend([0xC9, expected, 0xF0, 0x04, 0xA9, code, 0x85, result]);
+}
+
+/// Appends "<branch> +4; LDA #code; STA result", failing unless taken.
+fn expect_branch(program: &mut Vec<u8>, branch: u8, result: u8, code: u8) {
+    program.extend([branch, 0x04, 0xA9, code, 0x85, result]);
+}
+
+/// An NROM-128 image that checks a few official opcodes and every stable
+/// unofficial one, reporting like nestest does. Returns the image and the
+/// address of the final `JMP *`.
+fn fixture_rom() -> (Vec<u8>, u16) {
+    const BEQ: u8 = 0xF0;
+    const BCS: u8 = 0xB0;
+    const BVS: u8 = 0x70;
+
+    let mut p = vec![0xA9, 0x00, 0x85, 0x02, 0x85, 0x03];
+
+    // Official: ADC overflow, then a PHA/PLA round trip.
+    p.extend([0x18, 0xA9, 0x7F, 0x69, 0x01]);
+    expect_branch(&mut p, BVS, 0x02, 0x01);
+    expect_a(&mut p, 0x80, 0x02, 0x02);
+    p.extend([0xA9, 0x5A, 0x48, 0xA9, 0x00, 0x68]);
+    expect_a(&mut p, 0x5A, 0x02, 0x03);
+
+    // LAX $10
+    p.extend([0xA9, 0x5A, 0x85, 0x10, 0xA9, 0x00, 0xA2, 0x00, 0xA7, 0x10]);
+    expect_a(&mut p, 0x5A, 0x03, 0x01);
+    p.push(0x8A);
+    expect_a(&mut p, 0x5A, 0x03, 0x02);
+    // SAX $11
+    p.extend([0xA9, 0xF0, 0xA2, 0x3C, 0x87, 0x11, 0xA5, 0x11]);
+    expect_a(&mut p, 0x30, 0x03, 0x03);
+    // DCP $12
+    p.extend([0xA9, 0x05, 0x85, 0x12, 0xA9, 0x04, 0xC7, 0x12]);
+    expect_branch(&mut p, BEQ, 0x03, 0x04);
+    p.extend([0xA5, 0x12]);
+    expect_a(&mut p, 0x04, 0x03, 0x05);
+    // ISB $13
+    p.extend([0xA9, 0x0F, 0x85, 0x13, 0x38, 0xA9, 0x20, 0xE7, 0x13]);
+    expect_a(&mut p, 0x10, 0x03, 0x06);
+    // SLO $14
+    p.extend([0xA9, 0x41, 0x85, 0x14, 0xA9, 0x02, 0x07, 0x14]);
+    expect_a(&mut p, 0x82, 0x03, 0x07);
+    // RLA $15
+    p.extend([0xA9, 0x81, 0x85, 0x15, 0x18, 0xA9, 0xFF, 0x27, 0x15]);
+    expect_a(&mut p, 0x02, 0x03, 0x08);
+    // SRE $16
+    p.extend([0xA9, 0x03, 0x85, 0x16, 0xA9, 0xFF, 0x47, 0x16]);
+    expect_a(&mut p, 0xFE, 0x03, 0x09);
+    // RRA $17
+    p.extend([0xA9, 0x02, 0x85, 0x17, 0x18, 0xA9, 0x10, 0x67, 0x17]);
+    expect_a(&mut p, 0x11, 0x03, 0x0A);
+    // ANC #$80
+    p.extend([0xA9, 0xF0, 0x0B, 0x80]);
+    expect_branch(&mut p, BCS, 0x03, 0x0B);
+    expect_a(&mut p, 0x80, 0x03, 0x0C);
+    // ALR #$0F
+    p.extend([0xA9, 0xFF, 0x4B, 0x0F]);
+    expect_branch(&mut p, BCS, 0x03, 0x0D);
+    expect_a(&mut p, 0x07, 0x03, 0x0E);
+    // ARR #$FF
+    p.extend([0xA9, 0xFF, 0x18, 0x6B, 0xFF]);
+    expect_a(&mut p, 0x7F, 0x03, 0x0F);
+    // AXS #$10
+    p.extend([0xA9, 0xF0, 0xA2, 0x3C, 0xCB, 0x10, 0x8A]);
+    expect_a(&mut p, 0x20, 0x03, 0x10);
+    // SBC #$01 ($EB)
+    p.extend([0x38, 0xA9, 0x10, 0xEB, 0x01]);
+    expect_a(&mut p, 0x0F, 0x03, 0x11);
+    // NOP $10, NOP $C000, NOP, NOP #$FF: a wrong length derails the LDA.
+    p.extend([0x04, 0x10, 0x0C, 0x00, 0xC0, 0x1A, 0x80, 0xFF, 0xA9, 0x33]);
+    expect_a(&mut p, 0x33, 0x03, 0x12);
+
+    let end = 0xC000 + p.len() as u16;
+    p.extend([0x4C, end as u8, (end >> 8) as u8]);
+
+    let mut prg_rom = vec![0xEA; PRG_ROM_PAGE_SIZE];
+    prg_rom[..p.len()].copy_from_slice(&p);
+    // Reset vector at $FFFC, the last bytes of the mirrored bank.
+    prg_rom[0x3FFC] = 0x00;
+    prg_rom[0x3FFD] = 0xC0;
+
+    let mut image = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+    image.extend(prg_rom);
+    image.extend(vec![0; 8192]);
+    (image, end)
+}
+
+#[test]
+fn nestest_style_fixture() {
+    let (image, end) = fixture_rom();
+    let rom = Rom::new(&image).unwrap();
+
+    let (official, unofficial) = run_automated(rom, end);
+    assert_eq!(official, 0x00, "official opcode checks failed");
+    assert_eq!(unofficial, 0x00, "unofficial opcode checks failed");
+}